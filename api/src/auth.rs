@@ -0,0 +1,487 @@
+//! Bearer token authentication and per-client quotas.
+//!
+//! Clients are listed in a TOML token store, each with the SHA-256 of its token and optional
+//! limits of requests and requested packages per minute. The name of the authenticated client
+//! is passed down in the `X-Vmaas-Client` request header, which is what the access log prints.
+//! The same store authenticates gRPC calls, by their `authorization` metadata.
+//!
+//! Clients are identified by their token only. Client certificates, when the TLS listeners
+//! verify them, decide whether the connection is accepted, but do not name the client.
+
+use actix_service::{Service, Transform};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::header::{HeaderName, HeaderValue, AUTHORIZATION, RETRY_AFTER, WWW_AUTHENTICATE};
+use actix_web::{Error, FromRequest, HttpMessage, HttpRequest, HttpResponse};
+use futures::future::{ok, ready, Ready};
+use log::warn;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use crate::config::ConfigError;
+use crate::metrics;
+
+pub const CLIENT_HEADER: &str = "x-vmaas-client";
+/// Client name logged for requests without authentication
+const ANONYMOUS: &str = "-";
+const WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientConfig {
+    pub name: String,
+    /// Hex encoded SHA-256 of the token, e.g. output of `echo -n $TOKEN | sha256sum`
+    pub token_sha256: String,
+    pub requests_per_minute: Option<u64>,
+    pub packages_per_minute: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TokenFile {
+    #[serde(default)]
+    clients: Vec<ClientConfig>,
+}
+
+/// Why a request was refused, independent of the protocol
+#[derive(Debug)]
+pub enum Rejection {
+    Unauthorized(&'static str),
+    /// Quota exceeded, with seconds until the next window
+    RateLimited(&'static str, u64),
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rejection::Unauthorized(reason) => write!(f, "{}", reason),
+            Rejection::RateLimited(quota, _) => write!(f, "Quota of {} per minute exceeded", quota),
+        }
+    }
+}
+
+impl From<Rejection> for Error {
+    fn from(rejection: Rejection) -> Error {
+        let msg = rejection.to_string();
+        let res = match rejection {
+            Rejection::Unauthorized(_) => HttpResponse::Unauthorized()
+                .header(WWW_AUTHENTICATE, "Bearer")
+                .body(msg.clone()),
+            Rejection::RateLimited(_, retry_after) => HttpResponse::TooManyRequests()
+                .header(RETRY_AFTER, retry_after.to_string())
+                .body(msg.clone()),
+        };
+        InternalError::from_response(msg, res).into()
+    }
+}
+
+fn unauthorized(reason: &'static str) -> Rejection {
+    metrics::AUTH_REJECTIONS.with_label_values(&["unauthorized"]).inc();
+    Rejection::Unauthorized(reason)
+}
+
+/// Usage of the quotas in the current window
+struct Usage {
+    start: Instant,
+    requests: u64,
+    packages: u64,
+}
+
+pub struct ClientState {
+    config: ClientConfig,
+    usage: Mutex<Usage>,
+}
+
+impl ClientState {
+    /// Adds to the usage of the current window, rejected requests are not counted
+    fn charge(&self, requests: u64, packages: u64) -> Result<(), Rejection> {
+        let mut usage = self.usage.lock().unwrap();
+        let now = Instant::now();
+        if now.duration_since(usage.start) >= WINDOW {
+            *usage = Usage { start: now, requests: 0, packages: 0 };
+        }
+
+        let over = |limit: Option<u64>, used: u64, add: u64| matches!(limit, Some(l) if used + add > l);
+        let exceeded = if over(self.config.requests_per_minute, usage.requests, requests) {
+            Some("requests")
+        } else if over(self.config.packages_per_minute, usage.packages, packages) {
+            Some("packages")
+        } else {
+            None
+        };
+        if let Some(quota) = exceeded {
+            let retry_after = WINDOW.checked_sub(now.duration_since(usage.start)).unwrap_or_default();
+            metrics::AUTH_REJECTIONS.with_label_values(&["rate_limited"]).inc();
+            warn!("Client {} exceeded its {} quota", self.config.name, quota);
+            return Err(Rejection::RateLimited(quota, retry_after.as_secs() + 1));
+        }
+
+        usage.requests += requests;
+        usage.packages += packages;
+        Ok(())
+    }
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Known clients, keyed by the hash of their token
+pub struct TokenStore {
+    clients: HashMap<String, Arc<ClientState>>,
+}
+
+impl TokenStore {
+    pub fn load(path: &Path) -> Result<TokenStore, ConfigError> {
+        let data = std::fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+        let file: TokenFile = toml::from_str(&data).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))?;
+
+        let mut clients = HashMap::new();
+        for config in file.clients {
+            if config.name.is_empty() || HeaderValue::from_str(&config.name).is_err() {
+                return Err(ConfigError::Invalid(format!("invalid client name {:?}", config.name)));
+            }
+            let hash = config.token_sha256.to_ascii_lowercase();
+            if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(ConfigError::Invalid(format!("token_sha256 of client {} is not a SHA-256 hash", config.name)));
+            }
+            let state = ClientState {
+                config,
+                usage: Mutex::new(Usage { start: Instant::now(), requests: 0, packages: 0 }),
+            };
+            if clients.insert(hash, Arc::new(state)).is_some() {
+                return Err(ConfigError::Invalid("duplicate token in token store".to_string()));
+            }
+        }
+        Ok(TokenStore { clients })
+    }
+
+    /// Authenticates a request by the value of its authorization header and counts it
+    /// against the request quota of the client
+    pub fn authenticate(&self, authorization: Option<&str>, peer: &str) -> Result<Client, Rejection> {
+        let header = authorization.ok_or_else(|| unauthorized("Missing bearer token"))?;
+        let token = header.strip_prefix("Bearer ").ok_or_else(|| unauthorized("Malformed authorization header"))?;
+        let client = self.clients.get(&sha256_hex(token.trim().as_bytes())).ok_or_else(|| {
+            warn!("Rejected invalid token from {}", peer);
+            unauthorized("Invalid bearer token")
+        })?;
+        client.charge(1, 0)?;
+        Ok(Client(Some(client.clone())))
+    }
+}
+
+/// Client the request was authenticated as, anonymous when authentication is disabled
+#[derive(Clone, Default)]
+pub struct Client(Option<Arc<ClientState>>);
+
+impl Client {
+    pub fn name(&self) -> &str {
+        self.0.as_ref().map_or(ANONYMOUS, |c| c.config.name.as_str())
+    }
+
+    /// Counts the packages of an update request against the quota of the client
+    pub fn charge_packages(&self, packages: usize) -> Result<(), Rejection> {
+        match self.0 {
+            Some(ref state) => state.charge(0, packages as u64),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Client {
+    /// Client with the given quotas, outside of any token store
+    pub fn with_quotas(name: &str, requests_per_minute: Option<u64>, packages_per_minute: Option<u64>) -> Client {
+        let config = ClientConfig {
+            name: name.to_string(),
+            token_sha256: String::new(),
+            requests_per_minute,
+            packages_per_minute,
+        };
+        let usage = Mutex::new(Usage { start: Instant::now(), requests: 0, packages: 0 });
+        Client(Some(Arc::new(ClientState { config, usage })))
+    }
+}
+
+impl FromRequest for Client {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ok(req.extensions().get::<Client>().cloned().unwrap_or_default())
+    }
+}
+
+/// Middleware authenticating requests by their bearer token. Has to be outside `Logger`,
+/// so the access log sees the client name. Refused requests are only marked, `Reject`
+/// answers them, so the layers in between record them like any other response.
+pub struct Auth {
+    store: Option<Arc<TokenStore>>,
+    public_paths: Arc<Vec<String>>,
+}
+
+impl Auth {
+    /// Without a token store all requests are let through anonymously
+    pub fn new(store: Option<Arc<TokenStore>>, public_paths: Vec<String>) -> Self {
+        Auth { store, public_paths: Arc::new(public_paths) }
+    }
+}
+
+impl<S, B> Transform<S> for Auth
+    where
+        S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AuthMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AuthMiddleware {
+            service,
+            store: self.store.clone(),
+            public_paths: self.public_paths.clone(),
+        })
+    }
+}
+
+pub struct AuthMiddleware<S> {
+    service: S,
+    store: Option<Arc<TokenStore>>,
+    public_paths: Arc<Vec<String>>,
+}
+
+impl<S> AuthMiddleware<S> {
+    fn authenticate(&self, req: &ServiceRequest) -> Result<Client, Rejection> {
+        let store = match self.store {
+            Some(ref store) => store,
+            None => return Ok(Client::default()),
+        };
+        if self.public_paths.iter().any(|p| p == req.path()) {
+            return Ok(Client::default());
+        }
+
+        let header = req.headers().get(AUTHORIZATION).map(|h| h.to_str().unwrap_or(""));
+        store.authenticate(header, req.connection_info().remote().unwrap_or("unknown"))
+    }
+}
+
+impl<S, B> Service for AuthMiddleware<S>
+    where
+        S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output=Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+        let client = match self.authenticate(&req) {
+            Ok(client) => client,
+            Err(rejection) => {
+                req.extensions_mut().insert(rejection);
+                Client::default()
+            }
+        };
+
+        // Never trust the header coming from the client
+        let value = HeaderValue::from_str(client.name()).unwrap_or_else(|_| HeaderValue::from_static(ANONYMOUS));
+        req.headers_mut().insert(HeaderName::from_static(CLIENT_HEADER), value);
+        req.extensions_mut().insert(client);
+
+        Box::pin(self.service.call(req))
+    }
+}
+
+/// Middleware answering requests refused by `Auth`, has to be inside `Logger`, `Metrics`
+/// and `InFlight`
+pub struct Reject;
+
+impl<S, B> Transform<S> for Reject
+    where
+        S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RejectMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RejectMiddleware { service })
+    }
+}
+
+pub struct RejectMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for RejectMiddleware<S>
+    where
+        S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output=Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let rejection = req.extensions_mut().remove::<Rejection>();
+        match rejection {
+            Some(rejection) => Box::pin(ready(Ok(req.error_response(rejection)))),
+            None => Box::pin(self.service.call(req)),
+        }
+    }
+}
+
+/// Loads the token store, when authentication is enabled
+pub fn token_store(path: &Option<PathBuf>) -> Result<Option<Arc<TokenStore>>, ConfigError> {
+    match path {
+        Some(path) => Ok(Some(Arc::new(TokenStore::load(path)?))),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App};
+
+    const TOKEN: &str = "secret";
+
+    /// Token store with a single client and the given quotas
+    fn store(name: &str, requests_per_minute: Option<u64>, packages_per_minute: Option<u64>) -> Arc<TokenStore> {
+        let mut data = format!("[[clients]]\nname = {:?}\ntoken_sha256 = {:?}\n", name, sha256_hex(TOKEN.as_bytes()));
+        if let Some(limit) = requests_per_minute {
+            data += &format!("requests_per_minute = {}\n", limit);
+        }
+        if let Some(limit) = packages_per_minute {
+            data += &format!("packages_per_minute = {}\n", limit);
+        }
+        let path = std::env::temp_dir().join(format!("api-tokens-{}-{}.toml", std::process::id(), name));
+        std::fs::write(&path, data).unwrap();
+        Arc::new(TokenStore::load(&path).unwrap())
+    }
+
+    /// Name of the client the request reached the handler as
+    async fn whoami(req: HttpRequest) -> HttpResponse {
+        let name = req.headers().get(CLIENT_HEADER).unwrap().to_str().unwrap().to_string();
+        HttpResponse::Ok().body(name)
+    }
+
+    async fn packages(client: Client) -> Result<HttpResponse, Error> {
+        client.charge_packages(3)?;
+        Ok(HttpResponse::Ok().finish())
+    }
+
+    fn get(uri: &str, token: Option<&str>) -> test::TestRequest {
+        let mut req = test::TestRequest::get().uri(uri);
+        if let Some(token) = token {
+            req = req.header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+        req
+    }
+
+    macro_rules! app {
+        ($store:expr) => {
+            test::init_service(App::new()
+                .wrap(Reject)
+                .wrap(Auth::new(Some($store), vec!["/healthz".to_string()]))
+                .route("/whoami", web::get().to(whoami))
+                .route("/healthz", web::get().to(whoami))
+                .route("/packages", web::get().to(packages))
+            ).await
+        };
+    }
+
+    fn retry_after(res: &ServiceResponse) -> u64 {
+        res.headers().get(RETRY_AFTER).unwrap().to_str().unwrap().parse().unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn missing_or_invalid_token() {
+        let mut app = app!(store("alice", None, None));
+        for token in &[None, Some("wrong")] {
+            let res = test::call_service(&mut app, get("/whoami", *token).to_request()).await;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(res.headers().get(WWW_AUTHENTICATE).unwrap(), "Bearer");
+        }
+        let req = test::TestRequest::get().uri("/whoami").header(AUTHORIZATION, TOKEN).to_request();
+        assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::UNAUTHORIZED);
+
+        let res = test::read_response(&mut app, get("/whoami", Some(TOKEN)).to_request()).await;
+        assert_eq!(res, "alice".as_bytes());
+    }
+
+    #[actix_rt::test]
+    async fn public_path_without_token() {
+        let mut app = app!(store("alice", None, None));
+        let res = test::call_service(&mut app, get("/healthz", None).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(test::read_body(res).await, ANONYMOUS.as_bytes());
+    }
+
+    #[actix_rt::test]
+    async fn request_quota() {
+        let mut app = app!(store("limited", Some(2), None));
+        for _ in 0..2 {
+            assert_eq!(test::call_service(&mut app, get("/whoami", Some(TOKEN)).to_request()).await.status(), StatusCode::OK);
+        }
+        let res = test::call_service(&mut app, get("/whoami", Some(TOKEN)).to_request()).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!((1..=WINDOW.as_secs()).contains(&retry_after(&res)));
+        // Public paths do not count
+        assert_eq!(test::call_service(&mut app, get("/healthz", Some(TOKEN)).to_request()).await.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn package_quota() {
+        let mut app = app!(store("packages", None, Some(5)));
+        assert_eq!(test::call_service(&mut app, get("/packages", Some(TOKEN)).to_request()).await.status(), StatusCode::OK);
+        let res = test::call_service(&mut app, get("/packages", Some(TOKEN)).to_request()).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!((1..=WINDOW.as_secs()).contains(&retry_after(&res)));
+        // Requests without packages are still let through
+        assert_eq!(test::call_service(&mut app, get("/whoami", Some(TOKEN)).to_request()).await.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn forged_client_header_replaced() {
+        let mut app = app!(store("alice", None, None));
+        for (uri, token, expected) in &[("/whoami", Some(TOKEN), "alice"), ("/healthz", None, ANONYMOUS)] {
+            let req = get(uri, *token).header(CLIENT_HEADER, "admin").to_request();
+            let res = test::read_response(&mut app, req).await;
+            assert_eq!(res, expected.as_bytes());
+        }
+    }
+}
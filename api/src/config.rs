@@ -0,0 +1,487 @@
+use serde::Deserialize;
+use structopt::StructOpt;
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Command line flags, every one of them can also be set from the environment,
+/// the on/off flags with `true` or `false`. These take precedence over the values in the config file.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "api", about = "VMaaS API server")]
+pub struct Opts {
+    /// Path to the TOML config file
+    #[structopt(short, long, env = "VMAAS_CONFIG", parse(from_os_str))]
+    pub config: Option<PathBuf>,
+
+    /// Path to the sqlite database snapshot
+    #[structopt(long, env = "VMAAS_DB_PATH", parse(from_os_str))]
+    pub db_path: Option<PathBuf>,
+
+    /// Addresses to listen on, comma separated
+    #[structopt(long, env = "VMAAS_BIND", use_delimiter = true)]
+    pub bind: Option<Vec<SocketAddr>>,
+
+    /// Number of HTTP workers, defaults to the number of CPUs
+    #[structopt(long, env = "VMAAS_WORKERS")]
+    pub workers: Option<usize>,
+
+    /// Maximum size of JSON request bodies in bytes
+    #[structopt(long, env = "VMAAS_JSON_LIMIT")]
+    pub json_limit: Option<usize>,
+
+    /// Maximum number of items on single page of list responses
+    #[structopt(long, env = "VMAAS_MAX_PAGE_SIZE")]
+    pub max_page_size: Option<usize>,
+
+    /// Log filter in `env_logger` syntax, `RUST_LOG` takes precedence
+    #[structopt(long, env = "VMAAS_LOG_LEVEL")]
+    pub log_level: Option<String>,
+
+    /// When to reload the database snapshot: never, on_change or interval
+    #[structopt(long, env = "VMAAS_RELOAD_POLICY")]
+    pub reload_policy: Option<ReloadPolicy>,
+
+    /// Seconds between reload checks
+    #[structopt(long, env = "VMAAS_RELOAD_INTERVAL")]
+    pub reload_interval: Option<u64>,
+
+    /// Serve Swagger UI at /api/v3/docs [env: VMAAS_SWAGGER_UI]
+    #[structopt(long, overrides_with = "no-swagger-ui")]
+    pub swagger_ui: bool,
+
+    /// Do not serve Swagger UI
+    #[structopt(long, overrides_with = "swagger-ui")]
+    pub no_swagger_ui: bool,
+
+    /// Memory limit of cached update responses in bytes, 0 disables the cache
+    #[structopt(long, env = "VMAAS_RESPONSE_CACHE_SIZE")]
+    pub response_cache_size: Option<usize>,
+
+    /// Include the database snapshot in response bodies, next to the headers [env: VMAAS_SNAPSHOT_IN_BODY]
+    #[structopt(long, overrides_with = "no-snapshot-in-body")]
+    pub snapshot_in_body: bool,
+
+    /// Report the database snapshot in the headers only
+    #[structopt(long, overrides_with = "snapshot-in-body")]
+    pub no_snapshot_in_body: bool,
+
+    /// Token store of authenticated clients, authentication is disabled without it
+    #[structopt(long, env = "VMAAS_AUTH_TOKENS", parse(from_os_str))]
+    pub auth_tokens: Option<PathBuf>,
+
+    /// Seconds to wait for requests in flight on shutdown
+    #[structopt(long, env = "VMAAS_SHUTDOWN_TIMEOUT")]
+    pub shutdown_timeout: Option<u64>,
+
+    /// Addresses to listen on with TLS, comma separated
+    #[structopt(long, env = "VMAAS_TLS_BIND", use_delimiter = true)]
+    pub tls_bind: Option<Vec<SocketAddr>>,
+
+    /// PEM encoded certificate chain
+    #[structopt(long, env = "VMAAS_TLS_CERT", parse(from_os_str))]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM encoded private key, PKCS#8 or RSA
+    #[structopt(long, env = "VMAAS_TLS_KEY", parse(from_os_str))]
+    pub tls_key: Option<PathBuf>,
+
+    /// PEM encoded CA certificates client certificates are verified against. Certificates only
+    /// gate the connection, clients are still identified by their bearer token
+    #[structopt(long, env = "VMAAS_TLS_CLIENT_CA", parse(from_os_str))]
+    pub tls_client_ca: Option<PathBuf>,
+
+    /// Reject TLS clients without a valid certificate [env: VMAAS_TLS_REQUIRE_CLIENT_CERT]
+    #[structopt(long, overrides_with = "no-tls-require-client-cert")]
+    pub tls_require_client_cert: bool,
+
+    /// Accept TLS clients without a certificate
+    #[structopt(long, overrides_with = "tls-require-client-cert")]
+    pub no_tls_require_client_cert: bool,
+
+    /// Address to serve the gRPC interface on, disabled when unset. Uses TLS when it is configured
+    #[structopt(long, env = "VMAAS_GRPC_BIND")]
+    pub grpc_bind: Option<SocketAddr>,
+}
+
+/// Value of an on/off flag pair, falling back to the environment when neither is given.
+/// clap only reads the environment for options taking a value, so it is done here.
+fn flag(on: bool, off: bool, env: &str) -> Result<Option<bool>, ConfigError> {
+    if on || off {
+        return Ok(Some(on));
+    }
+    match std::env::var(env) {
+        Ok(value) => match value.as_str() {
+            "true" | "1" => Ok(Some(true)),
+            "false" | "0" => Ok(Some(false)),
+            _ => Err(ConfigError::Invalid(format!("{} must be true or false, not {:?}", env, value))),
+        },
+        Err(_) => Ok(None),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReloadPolicy {
+    /// Keep the snapshot loaded at startup
+    Never,
+    /// Reload when the modification time of the database file changes
+    OnChange,
+    /// Reload unconditionally every interval
+    Interval,
+}
+
+impl FromStr for ReloadPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(ReloadPolicy::Never),
+            "on_change" => Ok(ReloadPolicy::OnChange),
+            "interval" => Ok(ReloadPolicy::Interval),
+            _ => Err(format!("unknown reload policy {:?}, expected never, on_change or interval", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Reload {
+    pub policy: ReloadPolicy,
+    /// Seconds between reload checks
+    pub interval: u64,
+}
+
+impl Default for Reload {
+    fn default() -> Self {
+        Reload {
+            policy: ReloadPolicy::Never,
+            interval: 60,
+        }
+    }
+}
+
+impl Reload {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Auth {
+    /// TOML file listing the clients and their quotas
+    pub tokens: Option<PathBuf>,
+    /// Paths accessible without a token, e.g. health checks
+    pub public_paths: Vec<String>,
+}
+
+impl Default for Auth {
+    fn default() -> Self {
+        Auth {
+            tokens: None,
+            public_paths: vec!["/healthz".to_string(), "/readyz".to_string(), "/metrics".to_string()],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tls {
+    /// TLS listeners, TLS is disabled when empty
+    pub bind: Vec<SocketAddr>,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// Verify client certificates against these CAs. This only decides whether the connection
+    /// is accepted, requests are attributed to clients by their bearer token
+    pub client_ca: Option<PathBuf>,
+    pub require_client_cert: bool,
+    /// Seconds between checks of the certificate for changes, 0 disables reloading
+    pub reload_interval: u64,
+}
+
+impl Default for Tls {
+    fn default() -> Self {
+        Tls {
+            bind: vec![],
+            cert: PathBuf::new(),
+            key: PathBuf::new(),
+            client_ca: None,
+            require_client_cert: false,
+            reload_interval: 60,
+        }
+    }
+}
+
+impl Tls {
+    pub fn enabled(&self) -> bool {
+        !self.bind.is_empty()
+    }
+
+    pub fn reload_interval(&self) -> Duration {
+        Duration::from_secs(self.reload_interval)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub db_path: PathBuf,
+    pub bind: Vec<SocketAddr>,
+    pub workers: Option<usize>,
+    pub json_limit: usize,
+    pub max_page_size: usize,
+    pub log_level: String,
+    pub swagger_ui: bool,
+    pub response_cache_size: usize,
+    /// Include the database snapshot in response bodies, next to the headers
+    pub snapshot_in_body: bool,
+    /// Seconds to wait for requests in flight on shutdown
+    pub shutdown_timeout: u64,
+    /// Address of the gRPC interface, disabled when unset. Served over TLS when TLS listeners
+    /// are configured
+    pub grpc_bind: Option<SocketAddr>,
+    pub reload: Reload,
+    pub auth: Auth,
+    pub tls: Tls,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            db_path: PathBuf::from("./vmaas.db"),
+            bind: vec![SocketAddr::from(([127, 0, 0, 1], 1080))],
+            workers: None,
+            json_limit: 4 * 1024 * 1024,
+            max_page_size: upcache::prelude::DEFAULT_PAGE_SIZE,
+            log_level: "info".to_string(),
+            swagger_ui: false,
+            response_cache_size: 64 * 1024 * 1024,
+            snapshot_in_body: false,
+            shutdown_timeout: 30,
+            grpc_bind: None,
+            reload: Reload::default(),
+            auth: Auth::default(),
+            tls: Tls::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "could not read config file {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "invalid config file {}: {}", path.display(), e),
+            ConfigError::Invalid(msg) => write!(f, "invalid configuration: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Builds the configuration from defaults, the config file, environment and command line,
+    /// in increasing order of precedence
+    pub fn load(opts: Opts) -> Result<Config, ConfigError> {
+        let mut config = match opts.config {
+            Some(ref path) => {
+                let data = std::fs::read_to_string(path).map_err(|e| ConfigError::Read(path.clone(), e))?;
+                toml::from_str(&data).map_err(|e| ConfigError::Parse(path.clone(), e))?
+            }
+            None => Config::default(),
+        };
+
+        if let Some(db_path) = opts.db_path {
+            config.db_path = db_path;
+        }
+        if let Some(bind) = opts.bind {
+            config.bind = bind;
+        }
+        if let Some(workers) = opts.workers {
+            config.workers = Some(workers);
+        }
+        if let Some(json_limit) = opts.json_limit {
+            config.json_limit = json_limit;
+        }
+        if let Some(max_page_size) = opts.max_page_size {
+            config.max_page_size = max_page_size;
+        }
+        if let Some(log_level) = opts.log_level {
+            config.log_level = log_level;
+        }
+        if let Some(swagger_ui) = flag(opts.swagger_ui, opts.no_swagger_ui, "VMAAS_SWAGGER_UI")? {
+            config.swagger_ui = swagger_ui;
+        }
+        if let Some(response_cache_size) = opts.response_cache_size {
+            config.response_cache_size = response_cache_size;
+        }
+        if let Some(snapshot_in_body) = flag(opts.snapshot_in_body, opts.no_snapshot_in_body, "VMAAS_SNAPSHOT_IN_BODY")? {
+            config.snapshot_in_body = snapshot_in_body;
+        }
+        if let Some(policy) = opts.reload_policy {
+            config.reload.policy = policy;
+        }
+        if let Some(interval) = opts.reload_interval {
+            config.reload.interval = interval;
+        }
+        if let Some(shutdown_timeout) = opts.shutdown_timeout {
+            config.shutdown_timeout = shutdown_timeout;
+        }
+        if let Some(grpc_bind) = opts.grpc_bind {
+            config.grpc_bind = Some(grpc_bind);
+        }
+        if let Some(tokens) = opts.auth_tokens {
+            config.auth.tokens = Some(tokens);
+        }
+        if let Some(bind) = opts.tls_bind {
+            config.tls.bind = bind;
+        }
+        if let Some(cert) = opts.tls_cert {
+            config.tls.cert = cert;
+        }
+        if let Some(key) = opts.tls_key {
+            config.tls.key = key;
+        }
+        if let Some(client_ca) = opts.tls_client_ca {
+            config.tls.client_ca = Some(client_ca);
+        }
+        let require_client_cert = flag(opts.tls_require_client_cert, opts.no_tls_require_client_cert, "VMAAS_TLS_REQUIRE_CLIENT_CERT")?;
+        if let Some(require_client_cert) = require_client_cert {
+            config.tls.require_client_cert = require_client_cert;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if !self.db_path.is_file() {
+            return Err(ConfigError::Invalid(format!("database {} does not exist", self.db_path.display())));
+        }
+        if self.bind.is_empty() && self.tls.bind.is_empty() {
+            return Err(ConfigError::Invalid("no bind address".to_string()));
+        }
+        if self.workers == Some(0) {
+            return Err(ConfigError::Invalid("workers must be greater than 0".to_string()));
+        }
+        if self.json_limit == 0 {
+            return Err(ConfigError::Invalid("json_limit must be greater than 0".to_string()));
+        }
+        if self.max_page_size == 0 {
+            return Err(ConfigError::Invalid("max_page_size must be greater than 0".to_string()));
+        }
+        if self.reload.policy != ReloadPolicy::Never && self.reload.interval == 0 {
+            return Err(ConfigError::Invalid("reload interval must be greater than 0".to_string()));
+        }
+        if let Some(ref tokens) = self.auth.tokens {
+            if !tokens.is_file() {
+                return Err(ConfigError::Invalid(format!("token store {} does not exist", tokens.display())));
+            }
+        }
+        if self.tls.enabled() {
+            for (name, path) in &[("certificate", &self.tls.cert), ("key", &self.tls.key)] {
+                if !path.is_file() {
+                    return Err(ConfigError::Invalid(format!("TLS {} {} does not exist", name, path.display())));
+                }
+            }
+            if self.tls.require_client_cert && self.tls.client_ca.is_none() {
+                return Err(ConfigError::Invalid("require_client_cert needs client_ca".to_string()));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a file unique to the test into the temp directory
+    fn temp_file(name: &str, data: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("api-config-{}-{}", std::process::id(), name));
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    fn opts(args: &[&str]) -> Opts {
+        Opts::from_iter_safe(std::iter::once("api").chain(args.iter().copied())).unwrap()
+    }
+
+    fn invalid(args: &[&str]) -> String {
+        match Config::load(opts(args)) {
+            Err(ConfigError::Invalid(msg)) => msg,
+            res => panic!("expected invalid configuration, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn file_env_cli_precedence() {
+        let db = temp_file("precedence.db", "");
+        let file = temp_file("precedence.toml", &format!(
+            "db_path = {:?}\njson_limit = 1\nmax_page_size = 1\nworkers = 1\nsnapshot_in_body = true\n",
+            db,
+        ));
+        // The only test setting these variables, the others must not depend on them
+        std::env::set_var("VMAAS_JSON_LIMIT", "2");
+        std::env::set_var("VMAAS_MAX_PAGE_SIZE", "2");
+        std::env::set_var("VMAAS_SNAPSHOT_IN_BODY", "false");
+        let config = Config::load(opts(&["-c", file.to_str().unwrap(), "--max-page-size", "3"]));
+        let cli_flag = Config::load(opts(&["-c", file.to_str().unwrap(), "--snapshot-in-body"]));
+        std::env::remove_var("VMAAS_JSON_LIMIT");
+        std::env::remove_var("VMAAS_MAX_PAGE_SIZE");
+        std::env::remove_var("VMAAS_SNAPSHOT_IN_BODY");
+
+        let config = config.unwrap();
+        assert_eq!(config.workers, Some(1));
+        assert_eq!(config.json_limit, 2);
+        assert_eq!(config.max_page_size, 3);
+        assert!(!config.snapshot_in_body);
+        assert!(cli_flag.unwrap().snapshot_in_body);
+    }
+
+    #[test]
+    fn flag_pairs() {
+        let db = temp_file("flags.db", "");
+        let db = db.to_str().unwrap();
+        assert!(!Config::load(opts(&["--db-path", db])).unwrap().swagger_ui);
+        assert!(Config::load(opts(&["--db-path", db, "--swagger-ui"])).unwrap().swagger_ui);
+        assert!(!Config::load(opts(&["--db-path", db, "--swagger-ui", "--no-swagger-ui"])).unwrap().swagger_ui);
+        assert!(Config::load(opts(&["--db-path", db, "--no-swagger-ui", "--swagger-ui"])).unwrap().swagger_ui);
+    }
+
+    #[test]
+    fn validation_errors() {
+        let db = temp_file("validation.db", "");
+        let db = db.to_str().unwrap();
+        assert!(invalid(&["--db-path", "/nonexistent/vmaas.db"]).contains("does not exist"));
+        assert_eq!(invalid(&["--db-path", db, "--workers", "0"]), "workers must be greater than 0");
+        assert_eq!(invalid(&["--db-path", db, "--json-limit", "0"]), "json_limit must be greater than 0");
+        assert_eq!(invalid(&["--db-path", db, "--max-page-size", "0"]), "max_page_size must be greater than 0");
+        assert_eq!(
+            invalid(&["--db-path", db, "--reload-policy", "interval", "--reload-interval", "0"]),
+            "reload interval must be greater than 0",
+        );
+        assert!(invalid(&["--db-path", db, "--auth-tokens", "/nonexistent/tokens.toml"]).starts_with("token store"));
+        assert!(invalid(&["--db-path", db, "--tls-bind", "127.0.0.1:0"]).starts_with("TLS certificate"));
+        assert_eq!(
+            invalid(&["--db-path", db, "--tls-bind", "127.0.0.1:0", "--tls-cert", db, "--tls-key", db, "--tls-require-client-cert"]),
+            "require_client_cert needs client_ca",
+        );
+    }
+
+    #[test]
+    fn file_errors() {
+        let file = temp_file("unknown.toml", "unknown_field = 1\n");
+        assert!(matches!(Config::load(opts(&["-c", file.to_str().unwrap()])), Err(ConfigError::Parse(..))));
+        assert!(matches!(Config::load(opts(&["-c", "/nonexistent/api.toml"])), Err(ConfigError::Read(..))));
+    }
+}
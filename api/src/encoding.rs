@@ -0,0 +1,371 @@
+//! Content negotiation of response formats and zstd transfer compression.
+//!
+//! Responses are serialized as JSON by default, MessagePack or CBOR are returned when the
+//! client prefers them in the `Accept` header. Gzip, deflate and brotli are handled by the
+//! actix `Compress` middleware and request decoder, zstd, which actix does not support,
+//! is implemented here in both directions.
+
+use actix_service::{Service, Transform};
+use actix_web::dev::{Body, BodySize, MessageBody, Payload, ResponseBody, ServiceRequest, ServiceResponse};
+use actix_web::error::{ErrorInternalServerError, PayloadError};
+use actix_web::http::header::{HeaderMap, HeaderName, ACCEPT, CONTENT_LENGTH, ACCEPT_ENCODING, CONTENT_ENCODING, VARY};
+use actix_web::http::StatusCode;
+use actix_web::{web, Error, HttpMessage, HttpRequest, HttpResponse, Responder};
+use bytes::Bytes;
+use futures::future::{ok, ready, Ready};
+use futures::Stream;
+use serde::Serialize;
+
+use std::future::Future;
+use std::io::Write;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use crate::snapshot;
+use crate::state::State;
+
+/// Values of a list header with their quality, e.g. `gzip;q=0.8, zstd`
+fn preferences(headers: &HeaderMap, name: HeaderName) -> Vec<(String, f32)> {
+    let mut res = vec![];
+    for value in headers.get_all(name) {
+        let value = match value.to_str() {
+            Ok(value) => value,
+            Err(_) => continue,
+        };
+        for item in value.split(',') {
+            let mut parts = item.split(';');
+            let name = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+            if name.is_empty() {
+                continue;
+            }
+            let quality = parts
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .filter_map(|q| q.trim().parse::<f32>().ok())
+                .next()
+                .unwrap_or(1.0);
+            res.push((name, quality));
+        }
+    }
+    res
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+impl Format {
+    fn from_media_type(media_type: &str) -> Option<Format> {
+        match media_type {
+            "application/json" | "application/*" | "*/*" => Some(Format::Json),
+            "application/msgpack" | "application/x-msgpack" => Some(Format::MessagePack),
+            "application/cbor" => Some(Format::Cbor),
+            _ => None,
+        }
+    }
+
+    /// Most preferred supported format in the `Accept` header, JSON if there is none
+    pub fn negotiate(req: &HttpRequest) -> Format {
+        let mut best = (Format::Json, 0.0);
+        for (media_type, quality) in preferences(req.headers(), ACCEPT) {
+            match Format::from_media_type(&media_type) {
+                Some(format) if quality > best.1 => best = (format, quality),
+                _ => {}
+            }
+        }
+        best.0
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::MessagePack => "application/msgpack",
+            Format::Cbor => "application/cbor",
+        }
+    }
+
+    pub fn serialize<T: Serialize>(self, value: &T) -> Result<Vec<u8>, String> {
+        match self {
+            Format::Json => json::to_vec(value).map_err(|e| e.to_string()),
+            Format::MessagePack => rmp_serde::to_vec_named(value).map_err(|e| e.to_string()),
+            Format::Cbor => serde_cbor::to_vec(value).map_err(|e| e.to_string()),
+        }
+    }
+
+    pub fn response(self, body: Bytes) -> HttpResponse {
+        HttpResponse::Ok().content_type(self.content_type()).header(VARY, "Accept").body(body)
+    }
+}
+
+/// Response serialized in the format negotiated with the client
+pub struct Encoded<T>(pub T);
+
+impl<T: Serialize> Responder for Encoded<T> {
+    type Error = Error;
+    type Future = Ready<Result<HttpResponse, Error>>;
+
+    fn respond_to(self, req: &HttpRequest) -> Self::Future {
+        let format = Format::negotiate(req);
+        let snapshot = req.app_data::<web::Data<Arc<State>>>().and_then(|state| state.snapshot());
+        ready(snapshot::serialize(req, format, snapshot.as_deref(), &self.0)
+            .map(|body| format.response(body.into()))
+            .map_err(ErrorInternalServerError))
+    }
+}
+
+/// Whether zstd is at least as preferred as any other encoding in `Accept-Encoding`
+fn prefers_zstd(headers: &HeaderMap) -> bool {
+    let prefs = preferences(headers, ACCEPT_ENCODING);
+    let zstd = prefs.iter().find(|(name, _)| name == "zstd").map_or(0.0, |(_, q)| *q);
+    zstd > 0.0 && prefs.iter().all(|(_, q)| *q <= zstd)
+}
+
+/// Middleware decoding zstd request bodies and compressing responses with zstd when
+/// the client prefers it. Has to be wrapped inside `Compress`, which leaves responses
+/// with `Content-Encoding` already set alone.
+pub struct Zstd;
+
+impl<S, B> Transform<S> for Zstd
+    where
+        S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S::Future: 'static,
+        B: MessageBody + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<ZstdBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ZstdMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ZstdMiddleware { service })
+    }
+}
+
+pub struct ZstdMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for ZstdMiddleware<S>
+    where
+        S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S::Future: 'static,
+        B: MessageBody + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<ZstdBody<B>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output=Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+        let zstd_request = matches!(req.headers().get(CONTENT_ENCODING), Some(e) if e == "zstd");
+        if zstd_request {
+            let decoder = match zstd::stream::write::Decoder::new(vec![]) {
+                Ok(decoder) => decoder,
+                Err(e) => return Box::pin(ready(Err(ErrorInternalServerError(e)))),
+            };
+            let payload = ZstdPayload { payload: req.take_payload(), decoder: Some(decoder) };
+            req.set_payload(Payload::Stream(Box::pin(payload)));
+            req.headers_mut().remove(CONTENT_ENCODING);
+        }
+        let zstd_response = prefers_zstd(req.headers());
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let res = fut.await?;
+            let empty = matches!(res.response().body().size(), BodySize::None | BodySize::Empty);
+            let encode = zstd_response
+                && !empty
+                && !res.headers().contains_key(CONTENT_ENCODING)
+                && res.status() != StatusCode::NO_CONTENT
+                && res.status() != StatusCode::NOT_MODIFIED
+                && res.status() != StatusCode::SWITCHING_PROTOCOLS;
+            let encoder = match encode {
+                true => Some(zstd::stream::write::Encoder::new(vec![], 0).map_err(ErrorInternalServerError)?),
+                false => None,
+            };
+            Ok(res.map_body(move |head, body| {
+                if encoder.is_some() {
+                    head.headers_mut().insert(CONTENT_ENCODING, "zstd".parse().unwrap());
+                    head.headers_mut().append(VARY, "Accept-Encoding".parse().unwrap());
+                    head.headers_mut().remove(CONTENT_LENGTH);
+                }
+                ResponseBody::Body(ZstdBody { body, encoder })
+            }))
+        })
+    }
+}
+
+/// Request payload decompressed on the fly
+struct ZstdPayload {
+    payload: Payload,
+    decoder: Option<zstd::stream::write::Decoder<Vec<u8>>>,
+}
+
+impl Stream for ZstdPayload {
+    type Item = Result<Bytes, PayloadError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            let decoder = match this.decoder {
+                Some(ref mut decoder) => decoder,
+                None => return Poll::Ready(None),
+            };
+            match Pin::new(&mut this.payload).poll_next(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(Some(Ok(chunk))) => {
+                    if let Err(e) = decoder.write_all(&chunk) {
+                        return Poll::Ready(Some(Err(PayloadError::Io(e))));
+                    }
+                    let out = std::mem::take(decoder.get_mut());
+                    if !out.is_empty() {
+                        return Poll::Ready(Some(Ok(out.into())));
+                    }
+                }
+                Poll::Ready(None) => {
+                    let mut decoder = this.decoder.take().unwrap();
+                    if let Err(e) = decoder.flush() {
+                        return Poll::Ready(Some(Err(PayloadError::Io(e))));
+                    }
+                    let out = decoder.into_inner();
+                    if !out.is_empty() {
+                        return Poll::Ready(Some(Ok(out.into())));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Response body compressed on the fly, passed through unchanged without an encoder
+pub struct ZstdBody<B> {
+    body: ResponseBody<B>,
+    encoder: Option<zstd::stream::write::Encoder<Vec<u8>>>,
+}
+
+impl<B: MessageBody> MessageBody for ZstdBody<B> {
+    fn size(&self) -> BodySize {
+        match self.encoder {
+            Some(_) => BodySize::Stream,
+            None => self.body.size(),
+        }
+    }
+
+    fn poll_next(&mut self, cx: &mut Context) -> Poll<Option<Result<Bytes, Error>>> {
+        loop {
+            let encoder = match self.encoder {
+                Some(ref mut encoder) => encoder,
+                None => return self.body.poll_next(cx),
+            };
+            match self.body.poll_next(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(Some(Ok(chunk))) => {
+                    encoder.write_all(&chunk)?;
+                    let out = std::mem::take(encoder.get_mut());
+                    if !out.is_empty() {
+                        return Poll::Ready(Some(Ok(out.into())));
+                    }
+                }
+                Poll::Ready(None) => {
+                    let out = self.encoder.take().unwrap().finish()?;
+                    self.body = ResponseBody::Other(Body::None);
+                    return Poll::Ready(Some(Ok(out.into())));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+
+    async fn echo(value: web::Json<json::Value>) -> Encoded<json::Value> {
+        Encoded(value.into_inner())
+    }
+
+    fn value() -> json::Value {
+        json::json!({"package_list": ["kernel-1.0-1.el7.x86_64"], "latest_only": true})
+    }
+
+    fn vary(res: &ServiceResponse<impl MessageBody>) -> Vec<&str> {
+        let mut vary = res.headers().get_all(VARY).map(|v| v.to_str().unwrap()).collect::<Vec<_>>();
+        vary.sort_unstable();
+        vary
+    }
+
+    #[actix_rt::test]
+    async fn negotiated_formats() {
+        let mut app = test::init_service(App::new().wrap(Zstd).route("/echo", web::post().to(echo))).await;
+        for (accept, content_type) in &[
+            ("", "application/json"),
+            ("application/msgpack", "application/msgpack"),
+            ("application/json;q=0.5, application/cbor", "application/cbor"),
+        ] {
+            let mut req = test::TestRequest::post().uri("/echo").set_json(&value());
+            if !accept.is_empty() {
+                req = req.header(ACCEPT, *accept);
+            }
+            let res = test::call_service(&mut app, req.to_request()).await;
+            assert_eq!(res.headers().get("content-type").unwrap(), content_type);
+            assert_eq!(vary(&res), vec!["Accept"]);
+
+            let body = test::read_body(res).await;
+            let decoded: json::Value = match *content_type {
+                "application/msgpack" => rmp_serde::from_slice(&body).unwrap(),
+                "application/cbor" => serde_cbor::from_slice(&body).unwrap(),
+                _ => json::from_slice(&body).unwrap(),
+            };
+            assert_eq!(decoded, value());
+        }
+    }
+
+    #[actix_rt::test]
+    async fn zstd_request_body() {
+        let mut app = test::init_service(App::new().wrap(Zstd).route("/echo", web::post().to(echo))).await;
+        let body = zstd::encode_all(&json::to_vec(&value()).unwrap()[..], 0).unwrap();
+        let req = test::TestRequest::post().uri("/echo")
+            .header(CONTENT_ENCODING, "zstd")
+            .header("content-type", "application/json")
+            .set_payload(body)
+            .to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().get(CONTENT_ENCODING).is_none());
+        assert_eq!(json::from_slice::<json::Value>(&test::read_body(res).await).unwrap(), value());
+    }
+
+    #[actix_rt::test]
+    async fn zstd_response_when_preferred() {
+        let mut app = test::init_service(App::new().wrap(Zstd).route("/echo", web::post().to(echo))).await;
+        let req = test::TestRequest::post().uri("/echo")
+            .header(ACCEPT_ENCODING, "gzip;q=0.5, zstd")
+            .set_json(&value())
+            .to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert_eq!(res.headers().get(CONTENT_ENCODING).unwrap(), "zstd");
+        assert_eq!(vary(&res), vec!["Accept", "Accept-Encoding"]);
+        let body = zstd::decode_all(&test::read_body(res).await[..]).unwrap();
+        assert_eq!(json::from_slice::<json::Value>(&body).unwrap(), value());
+
+        let req = test::TestRequest::post().uri("/echo")
+            .header(ACCEPT_ENCODING, "gzip, zstd;q=0.5")
+            .set_json(&value())
+            .to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert!(res.headers().get(CONTENT_ENCODING).is_none());
+        assert_eq!(vary(&res), vec!["Accept"]);
+    }
+}
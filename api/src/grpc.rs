@@ -0,0 +1,631 @@
+//! gRPC interface, serving the same operations as the REST API from the shared cache.
+//!
+//! Runs on its own thread with a separate runtime, requests are translated into the calc
+//! requests used by the REST handlers and their responses back into protobuf messages.
+//! When TLS is configured, gRPC is served over it with the same certificate and client
+//! verification as the REST listeners.
+
+// `Status` is the error type of the generated service trait
+#![allow(clippy::result_large_err)]
+
+use futures::channel::{mpsc, oneshot};
+use futures::{FutureExt, Stream};
+use log::{debug, error, info, warn};
+use rustls::ServerConfig;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::task;
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+use tonic::transport::server::Connected;
+use tonic::{Request, Response, Status};
+
+use upcache::calc::updates::{self, Updates};
+use upcache::calc::{cves, dbchange, errata, repos, vulnerabilities};
+use upcache::cvss::{AttackVector, Vector};
+use upcache::prelude::{PagingInfo, Timestamp};
+use upcache::timestamp;
+use upcache::Cache;
+
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use crate::auth::{Client, Rejection, TokenStore};
+use crate::metrics;
+use crate::snapshot;
+use crate::state::{Loaded, State};
+
+pub mod proto {
+    tonic::include_proto!("vmaas.v3");
+}
+
+use proto::vmaas_server::{Vmaas, VmaasServer};
+
+impl From<Rejection> for Status {
+    fn from(rejection: Rejection) -> Status {
+        match rejection {
+            Rejection::Unauthorized(_) => Status::unauthenticated(rejection.to_string()),
+            Rejection::RateLimited(..) => Status::resource_exhausted(rejection.to_string()),
+        }
+    }
+}
+
+fn optional(value: String) -> Option<String> {
+    Some(value).filter(|v| !v.is_empty())
+}
+
+fn optional_timestamp(value: String) -> Result<Option<Timestamp>, Status> {
+    optional(value).map(|value| timestamp::parse(&value)).transpose()
+        .map_err(|e| Status::invalid_argument(e.to_string()))
+}
+
+fn format_timestamp(ts: Option<Timestamp>) -> String {
+    ts.as_ref().map(timestamp::format).unwrap_or_default()
+}
+
+fn optional_list<T>(list: Vec<T>) -> Option<Vec<T>> {
+    Some(list).filter(|l| !l.is_empty())
+}
+
+fn paging(paging: Option<proto::PagingInfo>, max_page_size: usize) -> PagingInfo {
+    let mut res = PagingInfo::default();
+    if let Some(paging) = paging {
+        if paging.page > 0 {
+            res.page = paging.page as usize;
+        }
+        if paging.page_size > 0 {
+            res.page_size = paging.page_size as usize;
+        }
+    }
+    res.limit(max_page_size);
+    res
+}
+
+fn page(page: upcache::prelude::Page) -> Option<proto::Page> {
+    Some(proto::Page {
+        page: page.paging.page as u64,
+        page_size: page.paging.page_size as u64,
+        pages: page.pages as u64,
+    })
+}
+
+fn module(module: updates::ModuleSpec) -> proto::ModuleSpec {
+    proto::ModuleSpec {
+        module_name: module.module_name,
+        module_stream: module.module_stream,
+    }
+}
+
+fn reason(reason: updates::Reason) -> i32 {
+    use proto::Reason as P;
+    use updates::Reason as R;
+
+    let res = match reason {
+        R::Accepted => P::Accepted,
+        R::UnknownName => P::UnknownName,
+        R::UnknownEvr => P::UnknownEvr,
+        R::NoUpdates => P::NoUpdates,
+        R::NoArchCompat => P::NoArchCompat,
+        R::PackageNotFound => P::PackageNotFound,
+        R::LatestVersion => P::LatestVersion,
+        R::NoErrata => P::NoErrata,
+        R::ArchMismatch => P::ArchMismatch,
+        R::ModuleDisabled => P::ModuleDisabled,
+        R::NoRepository => P::NoRepository,
+        R::ErratumNotInRepository => P::ErratumNotInRepository,
+        R::RepositoryNotAvailable => P::RepositoryNotAvailable,
+        R::ReleaseverMismatch => P::ReleaseverMismatch,
+        R::ProductMismatch => P::ProductMismatch,
+        R::Superseded => P::Superseded,
+    };
+    res as i32
+}
+
+impl From<proto::UpdatesRequest> for updates::Request {
+    fn from(req: proto::UpdatesRequest) -> Self {
+        updates::Request {
+            package_list: req.package_list,
+            repository_list: optional_list(req.repository_list),
+            modules_list: optional_list(req.modules_list).map(|modules| modules.into_iter()
+                .map(|m| updates::ModuleSpec { module_name: m.module_name, module_stream: m.module_stream })
+                .collect()),
+            releasever: optional(req.releasever),
+            basearch: optional(req.basearch),
+            latest_only: req.latest_only,
+            explain: req.explain,
+        }
+    }
+}
+
+impl From<updates::Response> for proto::UpdatesResponse {
+    fn from(res: updates::Response) -> Self {
+        let update_list = res.update_list.into_iter().map(|(name, detail)| {
+            let detail = proto::UpdatesPkgDetail {
+                summary: detail.summary.unwrap_or_default(),
+                description: detail.description.unwrap_or_default(),
+                available_updates: detail.available_updates.into_iter().map(|u| proto::PkgUpdate {
+                    package: u.package.to_string(),
+                    erratum: u.erratum,
+                    repository: u.repository.unwrap_or_default(),
+                    basearch: u.basearch.unwrap_or_default(),
+                    releasever: u.releasever,
+                    errata: u.errata,
+                }).collect(),
+                explanation: detail.explanation.map(|e| proto::Explanation {
+                    reason: e.reason.map_or(0, reason),
+                    candidates: e.candidates.into_iter().map(|c| proto::Candidate {
+                        package: c.package.to_string(),
+                        erratum: c.erratum.unwrap_or_default(),
+                        repository: c.repository.unwrap_or_default(),
+                        reason: reason(c.reason),
+                    }).collect(),
+                }),
+            };
+            (name, detail)
+        }).collect();
+
+        proto::UpdatesResponse {
+            update_list,
+            repository_list: res.repository_list.unwrap_or_default(),
+            modules_list: res.modules_list.unwrap_or_default().into_iter().map(module).collect(),
+            releasever: res.releasever.unwrap_or_default(),
+            basearch: res.basearch.unwrap_or_default(),
+            latest_only: res.latest_only,
+            unknown_modules_list: res.unknown_modules_list.into_iter().map(module).collect(),
+        }
+    }
+}
+
+fn cvss_vector<V: Vector>(vector: V) -> proto::CvssVector {
+    proto::CvssVector {
+        version: vector.version().to_string(),
+        metrics: vector.metrics().into_iter().map(|(m, v)| (m.to_string(), v.to_string())).collect(),
+        base_score: vector.base_score(),
+    }
+}
+
+fn attack_vector(av: proto::AttackVector) -> Option<AttackVector> {
+    use proto::AttackVector as P;
+
+    match av {
+        P::Unspecified => None,
+        P::Network => Some(AttackVector::Network),
+        P::AdjacentNetwork => Some(AttackVector::AdjacentNetwork),
+        P::Local => Some(AttackVector::Local),
+        P::Physical => Some(AttackVector::Physical),
+    }
+}
+
+impl From<vulnerabilities::Response> for proto::VulnerabilitiesResponse {
+    fn from(res: vulnerabilities::Response) -> Self {
+        let risk = proto::RiskSummary {
+            highest_cvss: res.risk.highest_cvss,
+            highest_cvss_cve: res.risk.highest_cvss_cve.unwrap_or_default(),
+            impact_counts: res.risk.impact_counts.into_iter().map(|(impact, count)| (impact, count as u64)).collect(),
+            fixable_count: res.risk.fixable_count as u64,
+            unpatched_count: res.risk.unpatched_count as u64,
+        };
+        let cvss_vectors = res.cvss_vectors.unwrap_or_default().into_iter().map(|(name, vectors)| {
+            let vectors = proto::CveVectors {
+                cvss3: vectors.cvss3.map(cvss_vector),
+                cvss2: vectors.cvss2.map(cvss_vector),
+            };
+            (name, vectors)
+        }).collect();
+
+        proto::VulnerabilitiesResponse {
+            cve_list: res.cve_list,
+            unpatched_cve_list: res.unpatched_cve_list,
+            risk: Some(risk),
+            cvss_vectors,
+        }
+    }
+}
+
+impl From<cves::Response> for proto::CvesResponse {
+    fn from(res: cves::Response) -> Self {
+        let cve_list = res.cve_list.into_iter().map(|(name, cve)| {
+            let detail = proto::CveDetail {
+                synopsis: cve.synopsis,
+                description: cve.description.unwrap_or_default(),
+                impact: cve.impact,
+                public_date: format_timestamp(cve.public_date),
+                modified_date: format_timestamp(cve.modified_date),
+                cwe_list: cve.cwe_list,
+                redhat_url: cve.redhat_url.unwrap_or_default(),
+                secondary_url: cve.secondary_url.unwrap_or_default(),
+                cvss3_score: cve.cvss3_score,
+                cvss3_metrics: cve.cvss3_metrics.unwrap_or_default(),
+                cvss2_score: cve.cvss2_score,
+                cvss2_metrics: cve.cvss2_metrics.unwrap_or_default(),
+                package_list: cve.package_list.iter().map(|p| p.to_string()).collect(),
+                errata_list: cve.errata_list,
+                source: cve.source,
+                cvss3_vector: cve.cvss3_vector.map(cvss_vector),
+                cvss2_vector: cve.cvss2_vector.map(cvss_vector),
+                cvss_errors: cve.cvss_errors,
+            };
+            (name, detail)
+        }).collect();
+
+        proto::CvesResponse {
+            cve_list,
+            modified_since: format_timestamp(res.modified_since),
+            page: page(res.page),
+        }
+    }
+}
+
+impl From<errata::Response> for proto::ErrataResponse {
+    fn from(res: errata::Response) -> Self {
+        let errata_list = res.errata_list.into_iter().map(|(name, erratum)| {
+            let detail = proto::ErratumDetail {
+                synopsis: erratum.synopsis,
+                summary: erratum.summary,
+                r#type: erratum.r#type,
+                severity: erratum.severity,
+                description: erratum.description.unwrap_or_default(),
+                solution: erratum.solution,
+                issued: format_timestamp(erratum.issued),
+                updated: format_timestamp(erratum.updated),
+                url: erratum.url,
+                cve_list: erratum.cve_list,
+                package_list: erratum.package_list.iter().map(|p| p.to_string()).collect(),
+                bugzilla_list: erratum.bugzilla_list,
+                reference_list: erratum.reference_list,
+            };
+            (name, detail)
+        }).collect();
+
+        proto::ErrataResponse {
+            errata_list,
+            modified_since: format_timestamp(res.modified_since),
+            page: page(res.page),
+        }
+    }
+}
+
+impl From<repos::Response> for proto::ReposResponse {
+    fn from(res: repos::Response) -> Self {
+        let repository_list = res.repository_list.into_iter().map(|(label, repos)| {
+            let repos = repos.into_iter().map(|r| proto::RepoDetail {
+                label: r.label,
+                name: r.name,
+                url: r.url,
+                basearch: r.basearch,
+                releasever: r.releasever,
+                product: r.product.unwrap_or_default(),
+                revision: format_timestamp(r.revision),
+            }).collect();
+            (label, proto::RepoList { repos })
+        }).collect();
+
+        proto::ReposResponse {
+            repository_list,
+            modified_since: format_timestamp(res.modified_since),
+            page: page(res.page),
+        }
+    }
+}
+
+impl From<dbchange::Response> for proto::DbChangeResponse {
+    fn from(res: dbchange::Response) -> Self {
+        proto::DbChangeResponse {
+            errata_changes: format_timestamp(res.errata_changes),
+            cve_changes: format_timestamp(res.cve_changes),
+            repository_changes: format_timestamp(res.repository_changes),
+            last_change: format_timestamp(res.last_change),
+            exported: format_timestamp(res.exported),
+        }
+    }
+}
+
+pub struct VmaasService {
+    state: Arc<State>,
+    store: Option<Arc<TokenStore>>,
+    max_page_size: usize,
+}
+
+impl VmaasService {
+    /// Authenticates the call and takes the loaded snapshot, the whole call is evaluated on it
+    fn begin<T>(&self, method: &str, req: &Request<T>) -> Result<(Client, Loaded), Status> {
+        self.authenticate(req).map_err(|status| record(method, status))
+    }
+
+    fn authenticate<T>(&self, req: &Request<T>) -> Result<(Client, Loaded), Status> {
+        let client = match self.store {
+            Some(ref store) => {
+                let header = req.metadata().get("authorization").map(|h| h.to_str().unwrap_or(""));
+                let peer = req.remote_addr().map_or("unknown".to_string(), |a| a.to_string());
+                store.authenticate(header, &peer)?
+            }
+            None => Client::default(),
+        };
+        let loaded = self.state.current().ok_or_else(|| Status::unavailable("Cache is not loaded yet"))?;
+        Ok((client, loaded))
+    }
+
+    /// Evaluates the call on the loaded cache, records the outcome and wraps the result,
+    /// stamped with the snapshot it was computed from
+    fn run<T, R: Into<T>>(&self, method: &str, loaded: &Loaded, call: impl FnOnce(&Cache) -> Result<R, Status>) -> Result<Response<T>, Status> {
+        let res = call(&loaded.cache).map_err(|status| record(method, status))?;
+        metrics::GRPC_REQUESTS.with_label_values(&[method, "ok"]).inc();
+
+        let mut res = Response::new(res.into());
+        let metadata = res.metadata_mut();
+        if let Some(Ok(exported)) = loaded.snapshot.exported.as_ref().map(|e| timestamp::format(e).parse()) {
+            metadata.insert(snapshot::EXPORTED_HEADER, exported);
+        }
+        if let Ok(hash) = loaded.snapshot.hash.parse() {
+            metadata.insert(snapshot::HASH_HEADER, hash);
+        }
+        Ok(res)
+    }
+}
+
+/// Counts a failed call
+fn record(method: &str, status: Status) -> Status {
+    let code = format!("{:?}", status.code()).to_lowercase();
+    metrics::GRPC_REQUESTS.with_label_values(&[method, &code]).inc();
+    status
+}
+
+#[tonic::async_trait]
+impl Vmaas for VmaasService {
+    async fn updates(&self, req: Request<proto::UpdatesRequest>) -> Result<Response<proto::UpdatesResponse>, Status> {
+        let (client, loaded) = self.begin("updates", &req)?;
+        self.run("updates", &loaded, |cache| {
+            client.charge_packages(req.get_ref().package_list.len())?;
+            metrics::UPDATES_PACKAGES.observe(req.get_ref().package_list.len() as f64);
+            // Evaluation is CPU heavy, other calls are moved to the remaining workers meanwhile
+            task::block_in_place(|| Updates::calc_updates(cache, req.into_inner().into()))
+                .map_err(|e| Status::internal(e.to_string()))
+        })
+    }
+
+    async fn vulnerabilities(&self, req: Request<proto::VulnerabilitiesRequest>) -> Result<Response<proto::VulnerabilitiesResponse>, Status> {
+        let (client, loaded) = self.begin("vulnerabilities", &req)?;
+        self.run("vulnerabilities", &loaded, |cache| {
+            let req = req.into_inner();
+            let req = vulnerabilities::Request {
+                system: req.system.unwrap_or_default().into(),
+                cvss_vectors: req.cvss_vectors,
+            };
+            client.charge_packages(req.system.package_list.len())?;
+            metrics::UPDATES_PACKAGES.observe(req.system.package_list.len() as f64);
+            task::block_in_place(|| vulnerabilities::get_vulnerabilities(cache, req))
+                .map_err(|e| Status::internal(e.to_string()))
+        })
+    }
+
+    async fn cves(&self, req: Request<proto::CvesRequest>) -> Result<Response<proto::CvesResponse>, Status> {
+        let (_, loaded) = self.begin("cves", &req)?;
+        self.run("cves", &loaded, |cache| {
+            let req = req.into_inner();
+            let attack_vectors = req.attack_vector_list.iter()
+                .map(|av| proto::AttackVector::from_i32(*av).and_then(attack_vector))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| Status::invalid_argument("Invalid attack vector"))?;
+            let req = cves::Request {
+                cve_list: req.cve_list,
+                modified_since: optional_timestamp(req.modified_since)?,
+                modified_until: optional_timestamp(req.modified_until)?,
+                published_since: optional_timestamp(req.published_since)?,
+                published_until: optional_timestamp(req.published_until)?,
+                cwe_list: optional_list(req.cwe_list),
+                impact_list: optional_list(req.impact_list),
+                cvss_from: req.cvss_from,
+                cvss_to: req.cvss_to,
+                attack_vector_list: optional_list(attack_vectors),
+                paging: paging(req.paging, self.max_page_size),
+            };
+            cves::get_cves(cache, req).map_err(|e| Status::invalid_argument(e.to_string()))
+        })
+    }
+
+    async fn errata(&self, req: Request<proto::ErrataRequest>) -> Result<Response<proto::ErrataResponse>, Status> {
+        let (_, loaded) = self.begin("errata", &req)?;
+        self.run("errata", &loaded, |cache| {
+            let req = req.into_inner();
+            let req = errata::Request {
+                errata_list: req.errata_list,
+                modified_since: optional_timestamp(req.modified_since)?,
+                paging: paging(req.paging, self.max_page_size),
+            };
+            errata::get_errata(cache, req).map_err(|e| Status::invalid_argument(e.to_string()))
+        })
+    }
+
+    async fn repos(&self, req: Request<proto::ReposRequest>) -> Result<Response<proto::ReposResponse>, Status> {
+        let (_, loaded) = self.begin("repos", &req)?;
+        self.run("repos", &loaded, |cache| {
+            let req = req.into_inner();
+            let req = repos::Request {
+                repository_list: req.repository_list,
+                modified_since: optional_timestamp(req.modified_since)?,
+                paging: paging(req.paging, self.max_page_size),
+            };
+            repos::get_repos(cache, req).map_err(|e| Status::invalid_argument(e.to_string()))
+        })
+    }
+
+    async fn db_change(&self, req: Request<proto::DbChangeRequest>) -> Result<Response<proto::DbChangeResponse>, Status> {
+        let (_, loaded) = self.begin("db_change", &req)?;
+        self.run("db_change", &loaded, |cache| Ok(dbchange::get_dbchange(cache)))
+    }
+}
+
+/// Running gRPC server, used to stop it on shutdown
+pub struct Handle {
+    stop: oneshot::Sender<()>,
+    stopped: oneshot::Receiver<()>,
+}
+
+impl Handle {
+    /// Stops accepting calls and resolves once the ones in progress are finished
+    pub async fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.stopped.await;
+    }
+}
+
+/// Connection with a completed TLS handshake
+struct TlsConnection(TlsStream<TcpStream>);
+
+impl Connected for TlsConnection {
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        self.0.get_ref().0.peer_addr().ok()
+    }
+}
+
+impl AsyncRead for TlsConnection {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TlsConnection {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_shutdown(cx)
+    }
+}
+
+/// Accepts connections and completes their handshakes concurrently, so a slow client
+/// does not hold up the others
+fn tls_incoming(mut listener: tokio::net::TcpListener, acceptor: TlsAcceptor) -> impl Stream<Item=io::Result<TlsConnection>> {
+    let (connections, incoming) = mpsc::unbounded();
+    tokio::spawn(async move {
+        loop {
+            let (tcp, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    let _ = connections.unbounded_send(Err(e));
+                    return;
+                }
+            };
+            let acceptor = acceptor.clone();
+            let connections = connections.clone();
+            tokio::spawn(async move {
+                match acceptor.accept(tcp).await {
+                    Ok(stream) => {
+                        let _ = connections.unbounded_send(Ok(TlsConnection(stream)));
+                    }
+                    Err(e) => debug!("TLS handshake with {} failed: {}", peer, e),
+                }
+            });
+        }
+    });
+    incoming
+}
+
+/// Starts serving gRPC on its own thread, until the returned handle is stopped or dropped.
+/// Uses TLS when given its configuration.
+pub fn spawn(
+    addr: SocketAddr,
+    state: Arc<State>,
+    store: Option<Arc<TokenStore>>,
+    tls: Option<ServerConfig>,
+    max_page_size: usize,
+) -> io::Result<Handle> {
+    // Bind right away, so errors are reported on startup
+    let listener = TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+    if tls.is_none() && store.is_some() {
+        warn!("Serving gRPC without TLS, bearer tokens are sent in plain text");
+    }
+    let acceptor = tls.map(|mut tls| {
+        tls.set_protocols(&[b"h2".to_vec()]);
+        TlsAcceptor::from(Arc::new(tls))
+    });
+    let (stop, stop_requested) = oneshot::channel::<()>();
+    let (finished, stopped) = oneshot::channel::<()>();
+    let service = VmaasService { state, store, max_page_size };
+
+    std::thread::spawn(move || {
+        let mut runtime = match tokio::runtime::Builder::new().threaded_scheduler().enable_all().build() {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                error!("Could not start gRPC runtime: {}", e);
+                std::process::exit(1);
+            }
+        };
+        let res = runtime.block_on(async move {
+            let mut listener = tokio::net::TcpListener::from_std(listener).map_err(|e| e.to_string())?;
+            let server = tonic::transport::Server::builder().add_service(VmaasServer::new(service));
+            match acceptor {
+                Some(acceptor) => {
+                    info!("Serving gRPC with TLS on {}", addr);
+                    server.serve_with_incoming_shutdown(tls_incoming(listener, acceptor), stop_requested.map(drop)).await
+                }
+                None => {
+                    info!("Serving gRPC on {}", addr);
+                    server.serve_with_incoming_shutdown(listener.incoming(), stop_requested.map(drop)).await
+                }
+            }.map_err(|e| e.to_string())
+        });
+        if let Err(e) = res {
+            error!("gRPC server failed: {}", e);
+            std::process::exit(1);
+        }
+        let _ = finished.send(());
+    });
+    Ok(Handle { stop, stopped })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proto::vmaas_client::VmaasClient;
+
+    /// Address with a free port
+    fn free_addr() -> SocketAddr {
+        TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn updates_round_trip() {
+        let state = crate::state::test_state();
+        let snapshot = state.snapshot().unwrap();
+        let addr = free_addr();
+        let server = spawn(addr, state, None, None, 100).unwrap();
+
+        let mut client = VmaasClient::connect(format!("http://{}", addr)).await.unwrap();
+        let res = client.updates(proto::UpdatesRequest {
+            package_list: vec!["kernel-1.0-1.el7.x86_64".to_string()],
+            repository_list: vec!["rhel-7-server-rpms".to_string()],
+            latest_only: true,
+            ..Default::default()
+        }).await.unwrap();
+        assert_eq!(res.metadata().get(snapshot::HASH_HEADER).unwrap(), snapshot.hash.as_str());
+
+        let res = res.into_inner();
+        assert!(res.latest_only);
+        assert_eq!(res.repository_list, vec!["rhel-7-server-rpms"]);
+        assert_eq!(res.update_list["kernel-1.0-1.el7.x86_64"].available_updates, vec![proto::PkgUpdate {
+            package: "kernel-1.2-1.el7.x86_64".to_string(),
+            erratum: "RHBA-2020:0002".to_string(),
+            repository: "rhel-7-server-rpms".to_string(),
+            basearch: "x86_64".to_string(),
+            releasever: "7Server".to_string(),
+            errata: vec!["RHBA-2020:0002".to_string(), "RHSA-2020:0001".to_string()],
+        }]);
+
+        let status = client.updates(proto::UpdatesRequest {
+            package_list: vec!["kernel-1.0-1.el7.bogus".to_string()],
+            ..Default::default()
+        }).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Internal);
+
+        server.stop().await;
+    }
+}
@@ -1,44 +1,436 @@
 use upcache::{
-    Cache,
     calc::updates::{
         Updates,
         Request as UpRequest,
         Response as UpResponse,
+        BatchRequest as UpBatchRequest,
+        BatchResponse as UpBatchResponse,
     },
+    calc::compat::{v1, v2},
+    calc::cves,
+    calc::errata,
+    calc::repos,
+    calc::vulnerabilities,
+    calc::dbchange,
+    prelude::{PagingInfo, Timestamp},
 };
 
-use log::info;
-use actix_web::{get, post, web, App, HttpServer, Responder, FromRequest};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use actix_web::{get, post, web, App, HttpRequest, HttpResponse, HttpServer, Result};
+use actix_web::error::{BlockingError, ErrorBadRequest, ErrorInternalServerError};
 use std::sync::Arc;
-use std::env;
+use bytes::Bytes;
 use actix_web::web::JsonConfig;
-use actix_web::middleware::Logger;
+use actix_web::middleware::{Compress, Logger};
+use actix_web::http::header::{ETag, ETAG, VARY};
+use structopt::StructOpt;
 
-type CacheData = web::Data<Arc<Cache>>;
+mod auth;
+mod config;
+mod encoding;
+mod grpc;
+mod metrics;
+mod ndjson;
+mod openapi;
+mod response_cache;
+mod shutdown;
+mod snapshot;
+mod state;
+mod tls;
+
+use crate::auth::Client;
+use crate::config::{Config, Opts};
+use crate::encoding::{Encoded, Format};
+use crate::state::State;
+
+type StateData = web::Data<Arc<State>>;
+type ConfigData = web::Data<Config>;
+
+/// Runs CPU heavy evaluation on the blocking thread pool, so the worker keeps serving other requests
+async fn blocking<T, F>(f: F) -> Result<T>
+    where
+        F: FnOnce() -> Result<T, String> + Send + 'static,
+        T: Send + 'static,
+{
+    web::block(f).await.map_err(|e| match e {
+        BlockingError::Error(e) => ErrorInternalServerError(e),
+        BlockingError::Canceled => ErrorInternalServerError("Evaluation was canceled"),
+    })
+}
 
 #[post("/api/v3/updates")]
-async fn updates(cache: CacheData, req: web::Json<UpRequest>) -> web::Json<UpResponse> {
-    web::Json(Updates::calc_updates(&cache.get_ref(), req.into_inner()).unwrap())
+async fn updates(state: StateData, client: Client, http: HttpRequest, req: web::Json<UpRequest>) -> Result<HttpResponse> {
+    let loaded = state.loaded_snapshot()?;
+    let format = Format::negotiate(&http);
+    let key = response_cache::request_key(&req, format.content_type());
+    let etag = loaded.snapshot.etag(&key);
+    // Neither the request nor the data changed since the client got the response
+    if snapshot::not_modified(&http, &etag) {
+        let mut res = HttpResponse::NotModified().set(ETag(etag)).header(VARY, "Accept").finish();
+        loaded.snapshot.stamp(res.headers_mut());
+        return Ok(res);
+    }
+
+    client.charge_packages(req.package_list.len())?;
+    metrics::UPDATES_PACKAGES.observe(req.package_list.len() as f64);
+    let body = match state.responses.get(loaded.generation, &key) {
+        Some(body) => body,
+        None => {
+            let cache = loaded.cache.clone();
+            let req = req.into_inner();
+            let res = blocking(move || Updates::calc_updates(&cache, req).map_err(|e| e.to_string())).await?;
+            let body = snapshot::serialize(&http, format, Some(&loaded.snapshot), &res).map_err(ErrorInternalServerError)?;
+            let body = Bytes::from(body);
+            state.responses.insert(loaded.generation, key, body.clone());
+            body
+        }
+    };
+    let mut res = format.response(body);
+    res.headers_mut().insert(ETAG, etag.to_string().parse().map_err(ErrorInternalServerError)?);
+    loaded.snapshot.stamp(res.headers_mut());
+    Ok(res)
+}
+
+/// Query parameters of the single package updates lookup
+#[derive(Debug, Deserialize)]
+struct UpdatesQuery {
+    /// Comma separated repository labels
+    repository_list: Option<String>,
+    releasever: Option<String>,
+    basearch: Option<String>,
+}
+
+#[get("/api/v3/updates/{nevra}")]
+async fn updates_nevra(state: StateData, client: Client, nevra: web::Path<String>, query: web::Query<UpdatesQuery>) -> Result<Encoded<UpResponse>> {
+    let cache = state.loaded()?;
+    let query = query.into_inner();
+    let req = UpRequest {
+        package_list: vec![nevra.into_inner()],
+        repository_list: query.repository_list.map(|r| r.split(',').map(String::from).collect()),
+        releasever: query.releasever,
+        basearch: query.basearch,
+        ..Default::default()
+    };
+    client.charge_packages(1)?;
+    metrics::UPDATES_PACKAGES.observe(1.0);
+    let res = blocking(move || Updates::calc_updates(&cache, req).map_err(|e| e.to_string())).await?;
+    Ok(Encoded(res))
+}
+
+#[post("/api/v3/cves")]
+async fn cve_list(state: StateData, config: ConfigData, req: web::Json<cves::Request>) -> Result<Encoded<cves::Response>> {
+    let cache = state.loaded()?;
+    let mut req = req.into_inner();
+    req.paging.limit(config.max_page_size);
+    let res = cves::get_cves(&cache, req).map_err(ErrorBadRequest)?;
+    Ok(Encoded(res))
+}
+
+#[get("/api/v3/cves/{cve}")]
+async fn cves_name(state: StateData, config: ConfigData, cve: web::Path<String>, paging: web::Query<PagingInfo>) -> Result<Encoded<cves::Response>> {
+    let cache = state.loaded()?;
+    let mut req = cves::Request {
+        cve_list: vec![cve.into_inner()],
+        paging: paging.into_inner(),
+        ..Default::default()
+    };
+    req.paging.limit(config.max_page_size);
+    let res = cves::get_cves(&cache, req).map_err(ErrorBadRequest)?;
+    Ok(Encoded(res))
+}
+
+#[post("/api/v3/errata")]
+async fn errata_list(state: StateData, config: ConfigData, req: web::Json<errata::Request>) -> Result<Encoded<errata::Response>> {
+    let cache = state.loaded()?;
+    let mut req = req.into_inner();
+    req.paging.limit(config.max_page_size);
+    let res = errata::get_errata(&cache, req).map_err(ErrorBadRequest)?;
+    Ok(Encoded(res))
+}
+
+#[get("/api/v3/errata/{erratum}")]
+async fn errata_name(state: StateData, config: ConfigData, erratum: web::Path<String>, paging: web::Query<PagingInfo>) -> Result<Encoded<errata::Response>> {
+    let cache = state.loaded()?;
+    let mut req = errata::Request {
+        errata_list: vec![erratum.into_inner()],
+        modified_since: None,
+        paging: paging.into_inner(),
+    };
+    req.paging.limit(config.max_page_size);
+    let res = errata::get_errata(&cache, req).map_err(ErrorBadRequest)?;
+    Ok(Encoded(res))
+}
+
+#[post("/api/v3/repos")]
+async fn repo_list(state: StateData, config: ConfigData, req: web::Json<repos::Request>) -> Result<Encoded<repos::Response>> {
+    let cache = state.loaded()?;
+    let mut req = req.into_inner();
+    req.paging.limit(config.max_page_size);
+    let res = repos::get_repos(&cache, req).map_err(ErrorBadRequest)?;
+    Ok(Encoded(res))
+}
+
+#[get("/api/v3/repos/{repo}")]
+async fn repos_name(state: StateData, config: ConfigData, repo: web::Path<String>, paging: web::Query<PagingInfo>) -> Result<Encoded<repos::Response>> {
+    let cache = state.loaded()?;
+    let mut req = repos::Request {
+        repository_list: vec![repo.into_inner()],
+        modified_since: None,
+        paging: paging.into_inner(),
+    };
+    req.paging.limit(config.max_page_size);
+    let res = repos::get_repos(&cache, req).map_err(ErrorBadRequest)?;
+    Ok(Encoded(res))
+}
+
+#[post("/api/v1/updates")]
+async fn updates_v1(state: StateData, client: Client, req: web::Json<UpRequest>) -> Result<Encoded<v1::Response>> {
+    let cache = state.loaded()?;
+    client.charge_packages(req.package_list.len())?;
+    metrics::UPDATES_PACKAGES.observe(req.package_list.len() as f64);
+    let req = req.into_inner();
+    let res = blocking(move || Updates::calc_updates(&cache, req).map_err(|e| e.to_string())).await?;
+    Ok(Encoded(v1::Response::from(&res)))
+}
+
+#[post("/api/v2/updates")]
+async fn updates_v2(state: StateData, client: Client, req: web::Json<UpRequest>) -> Result<Encoded<v2::Response>> {
+    let cache = state.loaded()?;
+    client.charge_packages(req.package_list.len())?;
+    metrics::UPDATES_PACKAGES.observe(req.package_list.len() as f64);
+    let req = req.into_inner();
+    let res = blocking(move || Updates::calc_updates(&cache, req).map_err(|e| e.to_string())).await?;
+    Ok(Encoded(v2::Response::from(&res)))
+}
+
+#[post("/api/v3/updates/batch")]
+async fn updates_batch(state: StateData, client: Client, req: web::Json<UpBatchRequest>) -> Result<Encoded<UpBatchResponse>> {
+    let cache = state.loaded()?;
+    client.charge_packages(req.systems.values().map(|s| s.package_list.len()).sum())?;
+    for system in req.systems.values() {
+        metrics::UPDATES_PACKAGES.observe(system.package_list.len() as f64);
+    }
+    let req = req.into_inner();
+    let res = blocking(move || Updates::calc_updates_batch(&cache, req).map_err(|e| e.to_string())).await?;
+    Ok(Encoded(res))
+}
+
+/// Streaming variant of the batch, systems are read and evaluated one NDJSON line at a time
+#[post("/api/v3/updates/stream")]
+async fn updates_stream(state: StateData, config: ConfigData, client: Client, payload: web::Payload) -> Result<HttpResponse> {
+    let cache = state.loaded()?;
+    let stream = ndjson::UpdatesStream::new(payload, cache, client, config.json_limit);
+    Ok(HttpResponse::Ok().content_type(ndjson::CONTENT_TYPE).streaming(stream))
+}
+
+#[post("/api/v3/vulnerabilities")]
+async fn vulnerability_list(state: StateData, client: Client, req: web::Json<vulnerabilities::Request>) -> Result<Encoded<vulnerabilities::Response>> {
+    let cache = state.loaded()?;
+    client.charge_packages(req.system.package_list.len())?;
+    metrics::UPDATES_PACKAGES.observe(req.system.package_list.len() as f64);
+    let req = req.into_inner();
+    let res = blocking(move || vulnerabilities::get_vulnerabilities(&cache, req).map_err(|e| e.to_string())).await?;
+    Ok(Encoded(res))
+}
+
+#[get("/api/v3/dbchange")]
+async fn dbchange_detail(state: StateData) -> Result<Encoded<dbchange::Response>> {
+    let cache = state.loaded()?;
+    Ok(Encoded(dbchange::get_dbchange(&cache)))
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Version {
+    version: &'static str,
+    exported: Option<Timestamp>,
+    schema_version: Option<i64>,
+}
+
+#[get("/api/v3/version")]
+async fn version(state: StateData) -> Encoded<Version> {
+    let cache = state.cache();
+    Encoded(Version {
+        version: env!("CARGO_PKG_VERSION"),
+        exported: cache.as_ref().and_then(|c| c.dbchange.get("exported").copied()),
+        schema_version: cache.as_ref().map(|c| c.schema_version),
+    })
+}
+
+/// Liveness, the process is able to serve requests
+#[get("/healthz")]
+async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().body("ok")
+}
+
+/// Readiness, the cache is loaded and requests can be answered
+#[get("/readyz")]
+async fn readyz(state: StateData) -> HttpResponse {
+    if shutdown::shutting_down() {
+        return HttpResponse::ServiceUnavailable().body("shutting down");
+    }
+    match state.cache() {
+        Some(_) => HttpResponse::Ok().body("ready"),
+        None => HttpResponse::ServiceUnavailable().body("loading"),
+    }
 }
 
 
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
-    env::set_var("RUST_LOG", "actix_web=trace,actix_server=debug,debug");
-    env_logger::init();
+    let config = match Config::load(Opts::from_args()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    env_logger::from_env(env_logger::Env::default().default_filter_or(&config.log_level)).init();
     info!("Starting up");
-    let cache = upcache::cache::load("./vmaas.db").unwrap();
-    let cache = Arc::new(cache);
+    let token_store = match auth::token_store(&config.auth.tokens) {
+        Ok(store) => store,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let tls_config = match config.tls.enabled() {
+        true => match tls::server_config(&config.tls) {
+            Ok(tls_config) => Some(tls_config),
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        false => None,
+    };
+    // Listen right away, the cache is loaded in the background and requests
+    // are answered with 503 until it is ready
+    let state = Arc::new(State::new(config.response_cache_size));
+    state::spawn_loader(state.clone(), config.db_path.clone(), config.reload.clone());
+
+    let grpc = match config.grpc_bind {
+        Some(addr) => match grpc::spawn(addr, state.clone(), token_store.clone(), tls_config.clone(), config.max_page_size) {
+            Ok(grpc) => Some(grpc),
+            Err(e) => {
+                error!("Could not serve gRPC on {}: {}", addr, e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
 
-    info!("Loaded cache");
-    HttpServer::new(move || App::new()
-        .wrap(Logger::new("%t|%s|%D ms|%a|%u"))
+    let public_paths = config.auth.public_paths.clone();
+    let json_limit = config.json_limit;
+    let swagger_ui = config.swagger_ui;
+    let config_data = config.clone();
+    let mut server = HttpServer::new(move || App::new()
+        .wrap(auth::Reject)
+        .wrap(encoding::Zstd)
+        .wrap(Compress::default())
+        .wrap(metrics::Metrics)
+        .wrap(Logger::new(&format!("%t|%s|%D ms|%a|%{{{}}}i", auth::CLIENT_HEADER)))
+        .wrap(shutdown::InFlight)
+        .wrap(auth::Auth::new(token_store.clone(), public_paths.clone()))
+        .wrap(snapshot::Stamp(state.clone()))
         .service(updates)
-        .app_data(JsonConfig::default().limit(4 * 1024 * 1024))
-        .data(cache.clone())
-    )
-        .bind("127.0.0.1:1080")?
-        .run()
-        .await?;
-    Ok(())
-}
\ No newline at end of file
+        .service(updates_batch)
+        .service(updates_stream)
+        .service(updates_v1)
+        .service(updates_v2)
+        .service(updates_nevra)
+        .service(cve_list)
+        .service(cves_name)
+        .service(errata_list)
+        .service(errata_name)
+        .service(repo_list)
+        .service(repos_name)
+        .service(vulnerability_list)
+        .service(dbchange_detail)
+        .service(version)
+        .service(healthz)
+        .service(readyz)
+        .service(metrics::metrics)
+        .service(openapi::openapi)
+        .configure(|cfg| if swagger_ui {
+            cfg.service(openapi::swagger_ui);
+        })
+        .app_data(JsonConfig::default().limit(json_limit))
+        .data(state.clone())
+        .data(config_data.clone())
+    );
+    if let Some(workers) = config.workers {
+        server = server.workers(workers);
+    }
+    for addr in config.bind.iter() {
+        server = server.bind(addr)?;
+    }
+    if let Some(tls_config) = tls_config {
+        for addr in config.tls.bind.iter() {
+            server = server.bind_rustls(addr, tls_config.clone())?;
+        }
+    }
+    // Signals are handled by us, to log the progress of draining
+    let server = server
+        .disable_signals()
+        .shutdown_timeout(config.shutdown_timeout)
+        .run();
+    actix_rt::spawn(shutdown::on_signal(server.clone(), grpc, config.shutdown_timeout));
+    server.await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{HeaderName, ACCEPT, IF_NONE_MATCH};
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    #[actix_rt::test]
+    async fn updates_not_modified() {
+        let state = state::test_state();
+        let snapshot = state.snapshot().unwrap();
+        let mut app = test::init_service(App::new()
+            .data(state)
+            .data(Config::default())
+            .service(updates)
+        ).await;
+        let request = || test::TestRequest::post().uri("/api/v3/updates").set_json(&json::json!({
+            "package_list": ["kernel-1.0-1.el7.x86_64"],
+            "repository_list": ["rhel-7-server-rpms"],
+        }));
+        let headers = |res: &actix_web::dev::ServiceResponse| -> Vec<Option<String>> {
+            [ETAG, VARY, HeaderName::from_static(snapshot::HASH_HEADER), HeaderName::from_static(snapshot::EXPORTED_HEADER)]
+                .iter()
+                .map(|name| res.headers().get(name).map(|v| v.to_str().unwrap().to_string()))
+                .collect()
+        };
+
+        let res = test::call_service(&mut app, request().to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let fresh = headers(&res);
+        let etag = fresh[0].clone().unwrap();
+        assert!(etag.starts_with("W/"));
+        assert_eq!(fresh[1..], [
+            Some("Accept".to_string()),
+            Some(snapshot.hash.clone()),
+            Some("2020-05-03T12:00:00.123456Z".to_string()),
+        ]);
+
+        let res = test::call_service(&mut app, request().header(IF_NONE_MATCH, etag.as_str()).to_request()).await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(headers(&res), fresh);
+        assert!(test::read_body(res).await.is_empty());
+
+        // Another format of the response is another entity
+        let res = test::call_service(&mut app, request()
+            .header(IF_NONE_MATCH, etag.as_str())
+            .header(ACCEPT, "application/msgpack")
+            .to_request()
+        ).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_ne!(headers(&res)[0], fresh[0]);
+    }
+}
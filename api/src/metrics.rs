@@ -0,0 +1,203 @@
+use actix_service::{Service, Transform};
+use actix_web::{dev::ServiceRequest, dev::ServiceResponse, get, Error, HttpRequest, HttpResponse};
+use futures::future::{ok, Ready};
+use lazy_static::lazy_static;
+use prometheus::{
+    register_gauge, register_histogram, register_int_gauge, register_histogram_vec, register_int_counter_vec,
+    register_int_gauge_vec, Encoder, Gauge, Histogram, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use upcache::Cache;
+
+use crate::StateData;
+
+lazy_static! {
+    pub static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "vmaas_http_requests_total", "Number of HTTP requests", &["route", "status"]
+    ).unwrap();
+    pub static ref HTTP_DURATION: HistogramVec = register_histogram_vec!(
+        "vmaas_http_request_duration_seconds", "Duration of HTTP requests", &["route"]
+    ).unwrap();
+    pub static ref HTTP_IN_FLIGHT: IntGauge = register_int_gauge!(
+        "vmaas_http_requests_in_flight", "Number of HTTP requests being processed"
+    ).unwrap();
+    pub static ref UPDATES_PACKAGES: Histogram = register_histogram!(
+        "vmaas_updates_packages", "Number of packages in update requests",
+        vec![1.0, 10.0, 100.0, 500.0, 1000.0, 2000.0, 5000.0, 10000.0]
+    ).unwrap();
+    pub static ref CACHE_LOAD_DURATION: Gauge = register_gauge!(
+        "vmaas_cache_load_duration_seconds", "Duration of the last successful cache load"
+    ).unwrap();
+    pub static ref CACHE_AGE: Gauge = register_gauge!(
+        "vmaas_cache_age_seconds", "Time since the loaded cache was exported"
+    ).unwrap();
+    pub static ref CACHE_MALFORMED_TIMESTAMPS: IntGauge = register_int_gauge!(
+        "vmaas_cache_malformed_timestamps", "Number of timestamps in the loaded cache which could not be parsed"
+    ).unwrap();
+    pub static ref CACHE_TABLE_SIZE: IntGaugeVec = register_int_gauge_vec!(
+        "vmaas_cache_table_size", "Number of entries in cache tables", &["table"]
+    ).unwrap();
+    pub static ref RELOADS: IntCounterVec = register_int_counter_vec!(
+        "vmaas_cache_reloads_total", "Number of cache reloads", &["result"]
+    ).unwrap();
+    pub static ref AUTH_REJECTIONS: IntCounterVec = register_int_counter_vec!(
+        "vmaas_auth_rejections_total", "Number of requests rejected by authentication or quotas", &["reason"]
+    ).unwrap();
+    pub static ref RESPONSE_CACHE: IntCounterVec = register_int_counter_vec!(
+        "vmaas_response_cache_lookups_total", "Number of update response cache lookups", &["result"]
+    ).unwrap();
+    pub static ref RESPONSE_CACHE_SIZE: IntGauge = register_int_gauge!(
+        "vmaas_response_cache_bytes", "Approximate size of cached update responses"
+    ).unwrap();
+    pub static ref GRPC_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "vmaas_grpc_requests_total", "Number of gRPC calls", &["method", "code"]
+    ).unwrap();
+}
+
+fn update_cache_metrics(cache: &Cache) {
+    if let Some(exported) = cache.dbchange.get("exported") {
+        let age = chrono::Utc::now().signed_duration_since(*exported);
+        CACHE_AGE.set(age.num_milliseconds() as f64 / 1000.0);
+    }
+
+    CACHE_MALFORMED_TIMESTAMPS.set(cache.malformed_timestamps as i64);
+    CACHE_TABLE_SIZE.with_label_values(&["packages"]).set(cache.pkg_details.len() as i64);
+    CACHE_TABLE_SIZE.with_label_values(&["repos"]).set(cache.repo_detail.len() as i64);
+    CACHE_TABLE_SIZE.with_label_values(&["errata"]).set(cache.errata_detail.len() as i64);
+    CACHE_TABLE_SIZE.with_label_values(&["cves"]).set(cache.cve_detail.len() as i64);
+}
+
+#[get("/metrics")]
+pub async fn metrics(state: StateData) -> HttpResponse {
+    if let Some(cache) = state.cache() {
+        update_cache_metrics(&cache);
+    }
+
+    let encoder = TextEncoder::new();
+    let mut buf = vec![];
+    encoder.encode(&prometheus::gather(), &mut buf).unwrap();
+    HttpResponse::Ok()
+        .content_type(encoder.format_type())
+        .body(buf)
+}
+
+/// Route pattern of the request, path parameters are replaced by their names to keep
+/// the number of label values bounded
+fn route(req: &HttpRequest) -> String {
+    if !req.resource_map().has_resource(req.path()) {
+        return "unmatched".to_string();
+    }
+    // Parameter values are slices of the matched path, which locates them even when
+    // another segment has the same text
+    let path = req.match_info().get_ref().path();
+    let mut params = req.match_info().iter()
+        .filter_map(|(name, value)| {
+            let start = (value.as_ptr() as usize).checked_sub(path.as_ptr() as usize)?;
+            Some((start, start + value.len(), name)).filter(|&(_, end, _)| end <= path.len())
+        })
+        .collect::<Vec<_>>();
+    params.sort();
+
+    let mut route = String::with_capacity(path.len());
+    let mut pos = 0;
+    for (start, end, name) in params {
+        if start < pos {
+            continue;
+        }
+        route.push_str(&path[pos..start]);
+        route.push('{');
+        route.push_str(name);
+        route.push('}');
+        pos = end;
+    }
+    route.push_str(&path[pos..]);
+    route
+}
+
+/// Middleware recording request counts and latencies per route
+pub struct Metrics;
+
+impl<S, B> Transform<S> for Metrics
+    where
+        S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = MetricsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(MetricsMiddleware { service })
+    }
+}
+
+pub struct MetricsMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for MetricsMiddleware<S>
+    where
+        S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output=Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let start = Instant::now();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let res = fut.await?;
+            let route = route(res.request());
+            let status = res.status();
+
+            HTTP_REQUESTS.with_label_values(&[&route, status.as_str()]).inc();
+            HTTP_DURATION.with_label_values(&[&route]).observe(start.elapsed().as_secs_f64());
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App};
+
+    async fn matched_route(req: HttpRequest) -> String {
+        route(&req)
+    }
+
+    #[actix_rt::test]
+    async fn route_replaces_parameter_segments() {
+        let mut app = test::init_service(App::new()
+            .route("/api/v3/repos/{repo}", web::get().to(matched_route))
+            .route("/api/v3/{kind}/{name}/x", web::get().to(matched_route))
+        ).await;
+        for (uri, expected) in &[
+            ("/api/v3/repos/repos", "/api/v3/repos/{repo}"),
+            ("/api/v3/repos/v3", "/api/v3/repos/{repo}"),
+            ("/api/v3/v3/v3/x", "/api/v3/{kind}/{name}/x"),
+        ] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let body = test::read_response(&mut app, req).await;
+            assert_eq!(body, expected.as_bytes(), "{}", uri);
+        }
+    }
+}
@@ -0,0 +1,279 @@
+//! Streaming batch evaluation over newline delimited JSON.
+//!
+//! Every input line is the update request of a single system, results are written out as
+//! soon as each system is evaluated. Input is only read when the client accepts more output,
+//! so memory use is bounded by the size of a single line, regardless of the batch size.
+//! Systems are evaluated on the blocking thread pool, one at a time.
+
+use actix_web::error::BlockingError;
+use actix_web::{web, Error};
+use bytes::{Bytes, BytesMut};
+use futures::future::{ready, FutureExt};
+use futures::Stream;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use upcache::calc::updates::{Request as UpRequest, Response as UpResponse, Updates};
+use upcache::Cache;
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use crate::auth::Client;
+use crate::metrics;
+
+pub const CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// Single input line, update request of one system
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct SystemRequest {
+    /// System ID, copied to the result
+    pub id: String,
+    #[serde(flatten)]
+    pub request: UpRequest,
+}
+
+/// Single output line, either the result or the error of one input line
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct SystemResponse {
+    /// Number of the input line, starting from 1
+    pub line: u64,
+    /// System ID, missing when the line could not be parsed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<UpResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl SystemResponse {
+    fn error(line: u64, id: Option<String>, error: String) -> Self {
+        SystemResponse { line, id, result: None, error: Some(error) }
+    }
+}
+
+type Evaluation = Pin<Box<dyn Future<Output=SystemResponse>>>;
+
+/// Response body evaluating request lines as the client reads the results
+pub struct UpdatesStream {
+    payload: web::Payload,
+    cache: Arc<Cache>,
+    client: Client,
+    max_line: usize,
+    buf: BytesMut,
+    /// Length of the buffer prefix already searched for a line end
+    scanned: usize,
+    line: u64,
+    /// Result of the last line, still being evaluated
+    pending: Option<Evaluation>,
+    eof: bool,
+    done: bool,
+}
+
+impl UpdatesStream {
+    pub fn new(payload: web::Payload, cache: Arc<Cache>, client: Client, max_line: usize) -> Self {
+        UpdatesStream {
+            payload,
+            cache,
+            client,
+            max_line,
+            buf: BytesMut::new(),
+            scanned: 0,
+            line: 0,
+            pending: None,
+            eof: false,
+            done: false,
+        }
+    }
+
+    /// Starts evaluating a single line, the stream ends when the quota of the client is exceeded
+    fn process(&mut self, line: &[u8]) -> Evaluation {
+        let line_no = self.line;
+        let system: SystemRequest = match json::from_slice(line) {
+            Ok(system) => system,
+            Err(e) => return Box::pin(ready(SystemResponse::error(line_no, None, format!("Invalid request: {}", e)))),
+        };
+        let packages = system.request.package_list.len();
+        if let Err(rejection) = self.client.charge_packages(packages) {
+            self.done = true;
+            return Box::pin(ready(SystemResponse::error(line_no, Some(system.id), rejection.to_string())));
+        }
+        metrics::UPDATES_PACKAGES.observe(packages as f64);
+
+        let cache = self.cache.clone();
+        let SystemRequest { id, request } = system;
+        Box::pin(web::block(move || Updates::calc_updates(&cache, request).map_err(|e| e.to_string())).map(move |res| {
+            match res {
+                Ok(res) => SystemResponse { line: line_no, id: Some(id), result: Some(res), error: None },
+                Err(BlockingError::Error(e)) => SystemResponse::error(line_no, Some(id), e),
+                Err(BlockingError::Canceled) => SystemResponse::error(line_no, Some(id), "Evaluation was canceled".to_string()),
+            }
+        }))
+    }
+
+    /// Ends the stream with an error of the given line
+    fn fail(&mut self, line: u64, error: String) -> Bytes {
+        self.done = true;
+        self.encode(&SystemResponse::error(line, None, error))
+    }
+
+    fn too_long(&mut self, line: u64) -> Bytes {
+        let error = format!("Line exceeds the limit of {} bytes", self.max_line);
+        self.fail(line, error)
+    }
+
+    fn encode(&self, res: &SystemResponse) -> Bytes {
+        let mut out = json::to_vec(res).unwrap_or_else(|e| {
+            json::to_vec(&SystemResponse::error(res.line, res.id.clone(), e.to_string())).unwrap_or_default()
+        });
+        out.push(b'\n');
+        Bytes::from(out)
+    }
+
+    /// Takes the next non-empty line out of the buffer, without the line end
+    fn next_line(&mut self) -> Option<BytesMut> {
+        loop {
+            let pos = self.buf[self.scanned..].iter().position(|b| *b == b'\n');
+            let line = match pos {
+                Some(pos) => {
+                    let mut line = self.buf.split_to(self.scanned + pos + 1);
+                    line.truncate(line.len() - 1);
+                    line
+                }
+                None if self.eof && !self.buf.is_empty() => self.buf.split(),
+                None => {
+                    self.scanned = self.buf.len();
+                    return None;
+                }
+            };
+            self.scanned = 0;
+            self.line += 1;
+            if !line.iter().all(u8::is_ascii_whitespace) {
+                return Some(line);
+            }
+        }
+    }
+}
+
+impl Stream for UpdatesStream {
+    type Item = Result<Bytes, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(ref mut pending) = this.pending {
+                let res = match pending.as_mut().poll(cx) {
+                    Poll::Ready(res) => res,
+                    Poll::Pending => return Poll::Pending,
+                };
+                this.pending = None;
+                return Poll::Ready(Some(Ok(this.encode(&res))));
+            }
+            if this.done {
+                return Poll::Ready(None);
+            }
+            if let Some(line) = this.next_line() {
+                if line.len() > this.max_line {
+                    return Poll::Ready(Some(Ok(this.too_long(this.line))));
+                }
+                this.pending = Some(this.process(&line));
+                continue;
+            }
+            if this.eof {
+                return Poll::Ready(None);
+            }
+            // No need to wait for the end of the line
+            if this.buf.len() > this.max_line {
+                return Poll::Ready(Some(Ok(this.too_long(this.line + 1))));
+            }
+
+            match Pin::new(&mut this.payload).poll_next(cx) {
+                Poll::Ready(Some(Ok(chunk))) => this.buf.extend_from_slice(&chunk),
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Ok(this.fail(this.line + 1, e.to_string())))),
+                Poll::Ready(None) => this.eof = true,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::dev::Payload;
+    use actix_web::error::PayloadError;
+    use futures::StreamExt;
+
+    /// Evaluates the request body delivered in the given chunks, returns the output lines
+    async fn evaluate(chunks: &[&str], client: Client, max_line: usize) -> Vec<json::Value> {
+        let chunks = chunks.iter().map(|c| Ok::<_, PayloadError>(Bytes::from(c.to_string()))).collect::<Vec<_>>();
+        let payload = web::Payload(Payload::Stream(Box::pin(futures::stream::iter(chunks))));
+        let cache = crate::state::test_state().cache().unwrap();
+        let stream = UpdatesStream::new(payload, cache, client, max_line);
+        let body = stream.map(|chunk| chunk.unwrap()).collect::<Vec<_>>().await.concat();
+        assert!(body.ends_with(b"\n"));
+        body.split(|b| *b == b'\n').filter(|l| !l.is_empty()).map(|l| json::from_slice(l).unwrap()).collect()
+    }
+
+    fn system(id: &str, package: &str) -> String {
+        format!(r#"{{"id": "{}", "package_list": ["{}"], "repository_list": ["rhel-7-server-rpms"]}}"#, id, package)
+    }
+
+    fn summary(lines: &[json::Value]) -> Vec<(u64, Option<&str>, bool)> {
+        lines.iter()
+            .map(|l| (l["line"].as_u64().unwrap(), l["id"].as_str(), l["result"].is_object()))
+            .collect()
+    }
+
+    #[actix_rt::test]
+    async fn lines_split_across_chunks() {
+        let body = format!("{}\n{}\n", system("a", "kernel-1.0-1.el7.x86_64"), system("b", "kernel-1.1-1.el7.x86_64"));
+        let (first, rest) = body.split_at(10);
+        let (second, third) = rest.split_at(body.find('\n').unwrap());
+        let lines = evaluate(&[first, second, third], Client::default(), 1024).await;
+        assert_eq!(summary(&lines), vec![(1, Some("a"), true), (2, Some("b"), true)]);
+        let updates = &lines[1]["result"]["update_list"]["kernel-1.1-1.el7.x86_64"]["available_updates"];
+        assert_eq!(updates[0]["package"], "kernel-1.2-1.el7.x86_64");
+    }
+
+    #[actix_rt::test]
+    async fn blank_lines_and_last_line_without_newline() {
+        let body = format!("\n{}\n  \r\n\n{}", system("a", "kernel-1.0-1.el7.x86_64"), system("b", "kernel-1.1-1.el7.x86_64"));
+        let lines = evaluate(&[&body], Client::default(), 1024).await;
+        assert_eq!(summary(&lines), vec![(2, Some("a"), true), (5, Some("b"), true)]);
+    }
+
+    #[actix_rt::test]
+    async fn invalid_lines_are_reported() {
+        let body = format!("not json\n{}\n", system("a", "kernel-1.0-1.el7.bogus"));
+        let lines = evaluate(&[&body], Client::default(), 1024).await;
+        assert_eq!(summary(&lines), vec![(1, None, false), (2, Some("a"), false)]);
+        assert!(lines[0]["error"].as_str().unwrap().starts_with("Invalid request"));
+        assert!(lines[1]["error"].as_str().unwrap().contains("arch_id not found"));
+    }
+
+    #[actix_rt::test]
+    async fn line_over_limit_ends_stream() {
+        let long = system("b", &"x".repeat(200));
+        let body = format!("{}\n{}\n{}\n", system("a", "kernel-1.0-1.el7.x86_64"), long, system("c", "kernel-1.0-1.el7.x86_64"));
+        // Whole line in a single chunk, and the limit reached before the line end arrives
+        let start = body.find('\n').unwrap() + 1;
+        for chunks in &[vec![body.as_str()], vec![&body[..start + 10], &body[start + 10..start + 150], &body[start + 150..]]] {
+            let lines = evaluate(chunks, Client::default(), 128).await;
+            assert_eq!(summary(&lines), vec![(1, Some("a"), true), (2, None, false)]);
+            assert_eq!(lines[1]["error"], "Line exceeds the limit of 128 bytes");
+        }
+    }
+
+    #[actix_rt::test]
+    async fn quota_ends_stream() {
+        let body = (0..4).map(|i| system(&i.to_string(), "kernel-1.0-1.el7.x86_64") + "\n").collect::<String>();
+        let client = Client::with_quotas("limited", None, Some(2));
+        let lines = evaluate(&[&body], client, 1024).await;
+        assert_eq!(summary(&lines), vec![(1, Some("0"), true), (2, Some("1"), true), (3, Some("2"), false)]);
+        assert_eq!(lines[2]["error"], "Quota of packages per minute exceeded");
+    }
+}
@@ -0,0 +1,209 @@
+use actix_web::{get, web, HttpResponse};
+use json::{json, Value};
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::JsonSchema;
+
+use upcache::calc::updates::{
+    Request as UpRequest,
+    Response as UpResponse,
+    BatchRequest as UpBatchRequest,
+    BatchResponse as UpBatchResponse,
+};
+use upcache::calc::compat::{v1, v2};
+use upcache::calc::{cves, dbchange, errata, repos, vulnerabilities};
+
+use crate::ndjson;
+use crate::Version;
+
+/// OpenAPI document builder, schemas of the request and response types are derived
+/// from their serde representation
+struct Spec {
+    gen: SchemaGenerator,
+    paths: json::Map<String, Value>,
+}
+
+impl Spec {
+    fn new() -> Self {
+        Spec {
+            gen: SchemaSettings::openapi3().into_generator(),
+            paths: json::Map::new(),
+        }
+    }
+
+    fn operation(&mut self, method: &str, path: &str, summary: &str, op: Value) -> &mut Self {
+        let mut op = op;
+        op["summary"] = summary.into();
+        self.paths.entry(path).or_insert_with(|| json!({}))[method] = op;
+        self
+    }
+
+    /// Response serialized as JSON, or in one of the binary formats negotiated with `Accept`
+    fn json_response<Res: JsonSchema>(&mut self) -> Value {
+        let schema = self.gen.subschema_for::<Res>();
+        json!({
+            "200": {
+                "description": "Success",
+                "content": {
+                    "application/json": { "schema": schema },
+                    "application/msgpack": { "schema": schema },
+                    "application/cbor": { "schema": schema },
+                }
+            }
+        })
+    }
+
+    fn post<Req: JsonSchema, Res: JsonSchema>(&mut self, path: &str, summary: &str) -> &mut Self {
+        let op = json!({
+            "requestBody": {
+                "required": true,
+                "content": { "application/json": { "schema": self.gen.subschema_for::<Req>() } }
+            },
+            "responses": self.json_response::<Res>(),
+        });
+        self.operation("post", path, summary, op)
+    }
+
+    /// POST operation taking and returning one JSON document per line
+    fn post_ndjson<Req: JsonSchema, Res: JsonSchema>(&mut self, path: &str, summary: &str) -> &mut Self {
+        let op = json!({
+            "requestBody": {
+                "required": true,
+                "content": { ndjson::CONTENT_TYPE: { "schema": self.gen.subschema_for::<Req>() } }
+            },
+            "responses": {
+                "200": {
+                    "description": "Success",
+                    "content": { ndjson::CONTENT_TYPE: { "schema": self.gen.subschema_for::<Res>() } }
+                }
+            },
+        });
+        self.operation("post", path, summary, op)
+    }
+
+    fn get<Res: JsonSchema>(&mut self, path: &str, summary: &str) -> &mut Self {
+        let op = json!({ "responses": self.json_response::<Res>() });
+        self.operation("get", path, summary, op)
+    }
+
+    /// GET operation with path and query parameters, given as `(name, location, description)`
+    fn get_params<Res: JsonSchema>(&mut self, path: &str, summary: &str, params: &[(&str, &str, &str)]) -> &mut Self {
+        let parameters = params.iter().map(|(name, location, description)| json!({
+            "name": name,
+            "in": location,
+            "required": *location == "path",
+            "description": description,
+            "schema": { "type": "string" },
+        })).collect::<Vec<_>>();
+
+        let op = json!({
+            "parameters": parameters,
+            "responses": self.json_response::<Res>(),
+        });
+        self.operation("get", path, summary, op)
+    }
+
+    fn get_text(&mut self, path: &str, summary: &str) -> &mut Self {
+        let op = json!({
+            "responses": {
+                "200": { "description": "Success", "content": { "text/plain": { "schema": { "type": "string" } } } },
+                "503": { "description": "Not available", "content": { "text/plain": { "schema": { "type": "string" } } } }
+            }
+        });
+        self.operation("get", path, summary, op)
+    }
+
+    fn finish(mut self) -> Value {
+        json!({
+            "openapi": "3.0.3",
+            "info": {
+                "title": "VMaaS",
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+            "paths": self.paths,
+            "components": { "schemas": self.gen.take_definitions() },
+        })
+    }
+}
+
+/// OpenAPI specification of all served routes
+pub fn spec() -> Value {
+    let mut spec = Spec::new();
+    spec.post::<UpRequest, UpResponse>("/api/v3/updates", "Calculate available updates of a system")
+        .post::<UpBatchRequest, UpBatchResponse>("/api/v3/updates/batch", "Calculate available updates of multiple systems")
+        .post_ndjson::<ndjson::SystemRequest, ndjson::SystemResponse>("/api/v3/updates/stream", "Calculate available updates of systems streamed one per line")
+        .post::<UpRequest, v1::Response>("/api/v1/updates", "Calculate available updates of a system, in the v1 format")
+        .post::<UpRequest, v2::Response>("/api/v2/updates", "Calculate available updates of a system, in the v2 format")
+        .get_params::<UpResponse>("/api/v3/updates/{nevra}", "Calculate available updates of a single package", &[
+            ("nevra", "path", "Package NEVRA"),
+            ("repository_list", "query", "Comma separated repository labels"),
+            ("releasever", "query", "Release version of the system"),
+            ("basearch", "query", "Base architecture of the system"),
+        ])
+        .post::<cves::Request, cves::Response>("/api/v3/cves", "Details of CVEs")
+        .get_params::<cves::Response>("/api/v3/cves/{cve}", "Details of CVEs matching a regular expression", &[
+            ("cve", "path", "CVE name or regular expression"),
+            ("page", "query", "Page number, starting from 1"),
+            ("page_size", "query", "Number of items on a page"),
+        ])
+        .post::<errata::Request, errata::Response>("/api/v3/errata", "Details of errata")
+        .get_params::<errata::Response>("/api/v3/errata/{erratum}", "Details of errata matching a regular expression", &[
+            ("erratum", "path", "Erratum name or regular expression"),
+            ("page", "query", "Page number, starting from 1"),
+            ("page_size", "query", "Number of items on a page"),
+        ])
+        .post::<repos::Request, repos::Response>("/api/v3/repos", "Details of repositories")
+        .get_params::<repos::Response>("/api/v3/repos/{repo}", "Details of repositories matching a regular expression", &[
+            ("repo", "path", "Repository label or regular expression"),
+            ("page", "query", "Page number, starting from 1"),
+            ("page_size", "query", "Number of items on a page"),
+        ])
+        .post::<vulnerabilities::Request, vulnerabilities::Response>("/api/v3/vulnerabilities", "CVEs a system is exposed to, with an aggregated risk summary")
+        .get::<dbchange::Response>("/api/v3/dbchange", "Timestamps of the last data changes")
+        .get::<Version>("/api/v3/version", "Version of the service and of the loaded data")
+        .get_text("/healthz", "Liveness check")
+        .get_text("/readyz", "Readiness check, fails until the data is loaded");
+    spec.finish()
+}
+
+#[get("/api/v3/openapi.json")]
+pub async fn openapi() -> web::Json<Value> {
+    web::Json(spec())
+}
+
+const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+  <title>VMaaS API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@3/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@3/swagger-ui-bundle.js"></script>
+  <script>SwaggerUIBundle({ url: "/api/v3/openapi.json", dom_id: "#swagger-ui" });</script>
+</body>
+</html>
+"##;
+
+#[get("/api/v3/docs")]
+pub async fn swagger_ui() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(SWAGGER_UI)
+}
+
+#[cfg(test)]
+mod tests {
+    const SPEC_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/openapi.json");
+
+    /// Published specification must match the one generated from the serde types.
+    /// Run with `UPDATE_OPENAPI=1` to regenerate it after changing them.
+    #[test]
+    fn spec_up_to_date() {
+        let generated = json::to_string_pretty(&super::spec()).unwrap() + "\n";
+        if std::env::var_os("UPDATE_OPENAPI").is_some() {
+            std::fs::write(SPEC_PATH, &generated).unwrap();
+        }
+        let published = std::fs::read_to_string(SPEC_PATH).unwrap();
+        assert!(published == generated, "{} is out of date, regenerate it with UPDATE_OPENAPI=1 cargo test -p api", SPEC_PATH);
+    }
+}
@@ -0,0 +1,251 @@
+//! Cache of serialized responses to repeated identical update requests.
+//!
+//! Entries are keyed by a hash of the canonical form of the request and tagged with the
+//! generation of the database snapshot they were computed from. A reload bumps the generation,
+//! which drops all entries, so stale responses are never served.
+
+use bytes::Bytes;
+use lru::LruCache;
+use sha2::{Digest, Sha256};
+use std::sync::Mutex;
+
+use upcache::calc::updates::Request;
+
+use crate::metrics;
+
+pub type Key = [u8; 32];
+
+/// Approximate bookkeeping overhead of a single entry, on top of the body
+const ENTRY_OVERHEAD: usize = 64;
+
+/// Hash of the request and the format of the response, independent of the order and duplicates
+/// in the package list, which do not affect the response
+pub fn request_key(req: &Request, format: &str) -> Key {
+    fn field(hasher: &mut Sha256, value: &str) {
+        hasher.input((value.len() as u64).to_le_bytes());
+        hasher.input(value.as_bytes());
+    }
+    fn optional(hasher: &mut Sha256, value: Option<&str>) {
+        match value {
+            Some(v) => {
+                hasher.input([1]);
+                field(hasher, v);
+            }
+            None => hasher.input([0]),
+        }
+    }
+
+    let mut packages: Vec<&str> = req.package_list.iter().map(String::as_str).collect();
+    packages.sort_unstable();
+    packages.dedup();
+
+    let mut hasher = Sha256::new();
+    field(&mut hasher, format);
+    hasher.input((packages.len() as u64).to_le_bytes());
+    for pkg in packages {
+        field(&mut hasher, pkg);
+    }
+    // Repositories and modules are echoed back in the response in the original order
+    match req.repository_list {
+        Some(ref repos) => {
+            hasher.input([1]);
+            hasher.input((repos.len() as u64).to_le_bytes());
+            for repo in repos {
+                field(&mut hasher, repo);
+            }
+        }
+        None => hasher.input([0]),
+    }
+    match req.modules_list {
+        Some(ref modules) => {
+            hasher.input([1]);
+            hasher.input((modules.len() as u64).to_le_bytes());
+            for module in modules {
+                field(&mut hasher, &module.module_name);
+                field(&mut hasher, &module.module_stream);
+            }
+        }
+        None => hasher.input([0]),
+    }
+    optional(&mut hasher, req.releasever.as_deref());
+    optional(&mut hasher, req.basearch.as_deref());
+    hasher.input([req.latest_only as u8, req.explain as u8]);
+
+    let mut key = Key::default();
+    key.copy_from_slice(&hasher.result());
+    key
+}
+
+struct Inner {
+    generation: u64,
+    entries: LruCache<Key, Bytes>,
+    size: usize,
+}
+
+impl Inner {
+    fn clear(&mut self, generation: u64) {
+        self.generation = generation;
+        self.entries.clear();
+        self.size = 0;
+    }
+}
+
+/// Least recently used responses, bounded by the total size of the stored bodies
+pub struct ResponseCache {
+    capacity: usize,
+    inner: Mutex<Inner>,
+}
+
+impl ResponseCache {
+    /// Cache holding at most `capacity` bytes, 0 disables caching
+    pub fn new(capacity: usize) -> Self {
+        ResponseCache {
+            capacity,
+            inner: Mutex::new(Inner {
+                generation: 0,
+                entries: LruCache::unbounded(),
+                size: 0,
+            }),
+        }
+    }
+
+    pub fn get(&self, generation: u64, key: &Key) -> Option<Bytes> {
+        if self.capacity == 0 {
+            return None;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        let res = match inner.generation == generation {
+            true => inner.entries.get(key).cloned(),
+            false => None,
+        };
+        let label = if res.is_some() { "hit" } else { "miss" };
+        metrics::RESPONSE_CACHE.with_label_values(&[label]).inc();
+        res
+    }
+
+    pub fn insert(&self, generation: u64, key: Key, body: Bytes) {
+        let entry_size = body.len() + ENTRY_OVERHEAD;
+        if entry_size > self.capacity {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        if generation < inner.generation {
+            // Computed from a snapshot which has been replaced in the meantime
+            return;
+        }
+        if generation > inner.generation {
+            inner.clear(generation);
+        }
+
+        if let Some(old) = inner.entries.put(key, body) {
+            inner.size -= old.len() + ENTRY_OVERHEAD;
+        }
+        inner.size += entry_size;
+        while inner.size > self.capacity {
+            match inner.entries.pop_lru() {
+                Some((_, evicted)) => inner.size -= evicted.len() + ENTRY_OVERHEAD,
+                None => break,
+            }
+        }
+        metrics::RESPONSE_CACHE_SIZE.set(inner.size as i64);
+    }
+
+    /// Drops all entries computed from snapshots older than `generation`
+    pub fn invalidate(&self, generation: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        if generation > inner.generation {
+            inner.clear(generation);
+            metrics::RESPONSE_CACHE_SIZE.set(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(n: u8) -> Key {
+        [n; 32]
+    }
+
+    fn body(len: usize) -> Bytes {
+        Bytes::from(vec![b'x'; len])
+    }
+
+    fn request(packages: &[&str]) -> Request {
+        Request {
+            package_list: packages.iter().map(|p| p.to_string()).collect(),
+            repository_list: Some(vec!["rhel-7-server-rpms".to_string()]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn evicts_least_recently_used_over_budget() {
+        let cache = ResponseCache::new(3 * (100 + ENTRY_OVERHEAD));
+        for n in 0..3 {
+            cache.insert(1, key(n), body(100));
+        }
+        assert!(cache.get(1, &key(0)).is_some());
+
+        cache.insert(1, key(3), body(100));
+        assert!(cache.get(1, &key(1)).is_none());
+        for n in &[0, 2, 3] {
+            assert!(cache.get(1, &key(*n)).is_some());
+        }
+
+        // Larger than the whole budget, never stored
+        cache.insert(1, key(4), body(3 * (100 + ENTRY_OVERHEAD)));
+        assert!(cache.get(1, &key(4)).is_none());
+        assert!(cache.get(1, &key(3)).is_some());
+    }
+
+    #[test]
+    fn drops_stale_generation_after_reload() {
+        let cache = ResponseCache::new(1 << 20);
+        cache.insert(1, key(0), body(10));
+        assert!(cache.get(1, &key(0)).is_some());
+
+        cache.invalidate(2);
+        assert!(cache.get(1, &key(0)).is_none());
+        assert!(cache.get(2, &key(0)).is_none());
+
+        // Response computed from the old snapshot, finishing after the reload
+        cache.insert(1, key(1), body(10));
+        assert!(cache.get(1, &key(1)).is_none());
+        assert!(cache.get(2, &key(1)).is_none());
+
+        cache.insert(2, key(2), body(10));
+        assert!(cache.get(2, &key(2)).is_some());
+    }
+
+    #[test]
+    fn disabled_with_zero_capacity() {
+        let cache = ResponseCache::new(0);
+        cache.insert(1, key(0), body(0));
+        assert!(cache.get(1, &key(0)).is_none());
+    }
+
+    #[test]
+    fn key_ignores_package_order_and_duplicates() {
+        let key = request_key(&request(&["a-1-1.x86_64", "b-1-1.x86_64"]), "application/json");
+        assert_eq!(key, request_key(&request(&["b-1-1.x86_64", "a-1-1.x86_64"]), "application/json"));
+        assert_eq!(key, request_key(&request(&["a-1-1.x86_64", "b-1-1.x86_64", "a-1-1.x86_64"]), "application/json"));
+        assert_ne!(key, request_key(&request(&["a-1-1.x86_64"]), "application/json"));
+
+        let mut latest = request(&["a-1-1.x86_64", "b-1-1.x86_64"]);
+        latest.latest_only = true;
+        assert_ne!(key, request_key(&latest, "application/json"));
+    }
+
+    #[test]
+    fn key_depends_on_format() {
+        let req = request(&["a-1-1.x86_64"]);
+        let json = request_key(&req, "application/json");
+        let msgpack = request_key(&req, "application/msgpack");
+        let cbor = request_key(&req, "application/cbor");
+        assert_ne!(json, msgpack);
+        assert_ne!(json, cbor);
+        assert_ne!(msgpack, cbor);
+    }
+}
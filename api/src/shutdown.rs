@@ -0,0 +1,148 @@
+//! Graceful shutdown on SIGTERM or SIGINT.
+//!
+//! Listeners are closed right away, requests in flight get up to the shutdown timeout to
+//! finish. Requests are tracked by a middleware, so the drain can be summarized in the log.
+
+use actix_rt::signal::unix::{signal, SignalKind};
+use actix_service::{Service, Transform};
+use actix_web::dev::{Server, ServiceRequest, ServiceResponse};
+use actix_web::Error;
+use actix_rt::time::timeout;
+use futures::future::{join, ok, select, Ready};
+use log::{info, warn};
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use crate::grpc;
+use crate::metrics;
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+static COMPLETED: AtomicU64 = AtomicU64::new(0);
+/// Requests dropped before a response was produced, e.g. after the shutdown timeout
+static ABORTED: AtomicU64 = AtomicU64::new(0);
+
+pub fn shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::SeqCst)
+}
+
+/// Tracks a single request from the call of the service until its response is ready
+struct InFlightGuard {
+    done: bool,
+}
+
+impl InFlightGuard {
+    fn new() -> Self {
+        metrics::HTTP_IN_FLIGHT.inc();
+        InFlightGuard { done: false }
+    }
+
+    fn finish(mut self) {
+        self.done = true;
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        metrics::HTTP_IN_FLIGHT.dec();
+        match self.done {
+            true => COMPLETED.fetch_add(1, Ordering::SeqCst),
+            false => ABORTED.fetch_add(1, Ordering::SeqCst),
+        };
+    }
+}
+
+/// Middleware counting requests in flight
+pub struct InFlight;
+
+impl<S, B> Transform<S> for InFlight
+    where
+        S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = InFlightMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(InFlightMiddleware { service })
+    }
+}
+
+pub struct InFlightMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for InFlightMiddleware<S>
+    where
+        S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output=Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let guard = InFlightGuard::new();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let res = fut.await;
+            guard.finish();
+            res
+        })
+    }
+}
+
+/// Waits for SIGTERM or SIGINT, then stops the servers gracefully and logs what was drained.
+/// Signal handling of actix itself has to be disabled.
+pub async fn on_signal(server: Server, grpc: Option<grpc::Handle>, shutdown_timeout: u64) {
+    let (mut term, mut int) = match (signal(SignalKind::terminate()), signal(SignalKind::interrupt())) {
+        (Ok(term), Ok(int)) => (term, int),
+        _ => {
+            warn!("Could not install signal handlers, graceful shutdown is disabled");
+            return;
+        }
+    };
+    let received = select(Box::pin(term.recv()), Box::pin(int.recv())).await;
+    let name = match received {
+        futures::future::Either::Left(_) => "SIGTERM",
+        futures::future::Either::Right(_) => "SIGINT",
+    };
+
+    SHUTTING_DOWN.store(true, Ordering::SeqCst);
+    let in_flight = metrics::HTTP_IN_FLIGHT.get();
+    let completed = COMPLETED.load(Ordering::SeqCst);
+    let aborted = ABORTED.load(Ordering::SeqCst);
+    info!("{} received, draining {} requests in flight, waiting at most {} s", name, in_flight, shutdown_timeout);
+
+    let start = Instant::now();
+    let grpc_stopped = async {
+        if let Some(grpc) = grpc {
+            if timeout(Duration::from_secs(shutdown_timeout), grpc.stop()).await.is_err() {
+                warn!("gRPC calls did not finish within {} s", shutdown_timeout);
+            }
+        }
+    };
+    join(server.stop(true), grpc_stopped).await;
+
+    let drained = COMPLETED.load(Ordering::SeqCst) - completed;
+    let abandoned = ABORTED.load(Ordering::SeqCst) - aborted + metrics::HTTP_IN_FLIGHT.get().max(0) as u64;
+    info!(
+        "Shutdown finished in {} ms, {} requests were in flight, {} completed while draining, {} abandoned",
+        start.elapsed().as_millis(), in_flight, drained, abandoned
+    );
+}
@@ -0,0 +1,201 @@
+//! Identification of the served database snapshot.
+//!
+//! Every response is stamped with the export timestamp and an identity of the snapshot
+//! it was computed from. Update responses also carry an ETag derived from the request and
+//! the snapshot, so polling clients get `304 Not Modified` until either of them changes.
+
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{EntityTag, Header, IfNoneMatch};
+use actix_web::http::{HeaderName, HeaderValue};
+use actix_web::{web, Error, HttpRequest};
+use futures::future::{ok, Ready};
+use schemars::JsonSchema;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use std::fs::Metadata;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::UNIX_EPOCH;
+
+use upcache::prelude::Timestamp;
+use upcache::{timestamp, Cache};
+
+use crate::config::Config;
+use crate::encoding::Format;
+use crate::response_cache::Key;
+use crate::state::State;
+
+pub const EXPORTED_HEADER: &str = "x-vmaas-snapshot-exported";
+pub const HASH_HEADER: &str = "x-vmaas-snapshot";
+
+/// Database snapshot a response was computed from
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct Snapshot {
+    /// Export timestamp recorded by reposcan
+    pub exported: Option<Timestamp>,
+    /// Identity of the snapshot, hex encoded SHA-256 of the export timestamp and schema version
+    pub hash: String,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl Snapshot {
+    /// Identifies the cache by the export timestamp and schema version, which reposcan sets on
+    /// every export, so replicas serving the same export agree without reading the whole file.
+    /// Databases without the timestamp fall back to the size and modification time of the file,
+    /// taken before loading, so a file replaced in the meantime is detected on the next reload.
+    pub fn new(cache: &Cache, file: &Metadata) -> Self {
+        let exported = cache.dbchange.get("exported").copied();
+        let mut hasher = Sha256::new();
+        hasher.input(cache.schema_version.to_le_bytes());
+        match exported {
+            Some(ref exported) => {
+                hasher.input([1]);
+                hasher.input(timestamp::format(exported).as_bytes());
+            }
+            None => {
+                let mtime = file.modified().ok().and_then(|m| m.duration_since(UNIX_EPOCH).ok()).unwrap_or_default();
+                hasher.input([0]);
+                hasher.input(file.len().to_le_bytes());
+                hasher.input(mtime.as_nanos().to_le_bytes());
+            }
+        }
+        Snapshot { exported, hash: hex(&hasher.result()) }
+    }
+
+    /// Weak ETag of a response to the request with the given key
+    pub fn etag(&self, key: &Key) -> EntityTag {
+        let mut hasher = Sha256::new();
+        hasher.input(self.hash.as_bytes());
+        hasher.input(key);
+        EntityTag::weak(hex(&hasher.result()))
+    }
+
+    pub fn stamp(&self, headers: &mut actix_web::http::HeaderMap) {
+        if let Some(Ok(exported)) = self.exported.as_ref().map(|e| HeaderValue::from_str(&timestamp::format(e))) {
+            headers.insert(HeaderName::from_static(EXPORTED_HEADER), exported);
+        }
+        if let Ok(hash) = HeaderValue::from_str(&self.hash) {
+            headers.insert(HeaderName::from_static(HASH_HEADER), hash);
+        }
+    }
+}
+
+/// Serializes a response body, along with the snapshot when enabled in the config
+pub fn serialize<T: Serialize>(req: &HttpRequest, format: Format, snapshot: Option<&Snapshot>, value: &T) -> Result<Vec<u8>, String> {
+    let enabled = matches!(req.app_data::<web::Data<Config>>(), Some(config) if config.snapshot_in_body);
+    match snapshot {
+        Some(snapshot) if enabled => {
+            let mut value = json::to_value(value).map_err(|e| e.to_string())?;
+            if let json::Value::Object(ref mut map) = value {
+                map.insert("snapshot".to_string(), json::to_value(snapshot).map_err(|e| e.to_string())?);
+            }
+            format.serialize(&value)
+        }
+        _ => format.serialize(value),
+    }
+}
+
+/// Whether the client already has the response with the tag
+pub fn not_modified(req: &HttpRequest, etag: &EntityTag) -> bool {
+    match IfNoneMatch::parse(req) {
+        Ok(IfNoneMatch::Any) => true,
+        Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        Err(_) => false,
+    }
+}
+
+/// Middleware stamping responses with the snapshot served when the request arrived
+pub struct Stamp(pub Arc<State>);
+
+impl<S, B> Transform<S> for Stamp
+    where
+        S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = StampMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(StampMiddleware { service, state: self.0.clone() })
+    }
+}
+
+pub struct StampMiddleware<S> {
+    service: S,
+    state: Arc<State>,
+}
+
+impl<S, B> Service for StampMiddleware<S>
+    where
+        S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output=Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let snapshot = self.state.snapshot();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let mut res = fut.await?;
+            // Handlers which know the exact snapshot they used stamp the response themselves
+            if let Some(snapshot) = snapshot {
+                if !res.headers().contains_key(HASH_HEADER) {
+                    snapshot.stamp(res.headers_mut());
+                }
+            }
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(data: &str) -> Metadata {
+        let path = upcache::testing::temp_path("snapshot");
+        std::fs::write(&path, data).unwrap();
+        let metadata = std::fs::metadata(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        metadata
+    }
+
+    #[test]
+    fn identity_of_export() {
+        let mut cache = upcache::testing::load().unwrap();
+        let (small, large) = (metadata("a"), metadata("ab"));
+        let snapshot = Snapshot::new(&cache, &small);
+        assert_eq!(snapshot.exported.as_ref().map(timestamp::format), Some("2020-05-03T12:00:00.123456Z".to_string()));
+        // Copies of the same export are the same snapshot
+        assert_eq!(snapshot.hash, Snapshot::new(&cache, &large).hash);
+
+        cache.schema_version += 1;
+        assert_ne!(snapshot.hash, Snapshot::new(&cache, &small).hash);
+
+        cache.dbchange.remove("exported");
+        let unknown = Snapshot::new(&cache, &small);
+        assert!(unknown.exported.is_none());
+        assert_ne!(unknown.hash, Snapshot::new(&cache, &large).hash);
+    }
+}
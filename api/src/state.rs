@@ -0,0 +1,136 @@
+use upcache::Cache;
+
+use actix_web::error::ErrorServiceUnavailable;
+use log::{info, error};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Instant, SystemTime};
+
+use crate::config::{Reload, ReloadPolicy};
+use crate::metrics;
+use crate::response_cache::ResponseCache;
+use crate::snapshot::Snapshot;
+
+/// Loaded database snapshot
+#[derive(Clone)]
+pub struct Loaded {
+    /// Incremented on every replace
+    pub generation: u64,
+    pub cache: Arc<Cache>,
+    pub snapshot: Arc<Snapshot>,
+}
+
+/// Currently served database snapshot, replaced as a whole on reload.
+/// Empty until the initial load finishes.
+pub struct State {
+    loaded: RwLock<Option<Loaded>>,
+    pub responses: ResponseCache,
+}
+
+impl State {
+    pub fn new(response_cache_size: usize) -> Self {
+        State {
+            loaded: RwLock::new(None),
+            responses: ResponseCache::new(response_cache_size),
+        }
+    }
+
+    pub fn cache(&self) -> Option<Arc<Cache>> {
+        self.loaded.read().unwrap().as_ref().map(|loaded| loaded.cache.clone())
+    }
+
+    pub fn snapshot(&self) -> Option<Arc<Snapshot>> {
+        self.loaded.read().unwrap().as_ref().map(|loaded| loaded.snapshot.clone())
+    }
+
+    /// Loaded cache, or `503 Service Unavailable` while it is still loading
+    pub fn loaded(&self) -> actix_web::Result<Arc<Cache>> {
+        self.cache().ok_or_else(|| ErrorServiceUnavailable("Cache is not loaded yet"))
+    }
+
+    /// Cache along with the generation and identification of its snapshot, unset while loading
+    pub fn current(&self) -> Option<Loaded> {
+        self.loaded.read().unwrap().clone()
+    }
+
+    /// Same as `loaded`, along with the generation and identification of the snapshot
+    pub fn loaded_snapshot(&self) -> actix_web::Result<Loaded> {
+        self.current().ok_or_else(|| ErrorServiceUnavailable("Cache is not loaded yet"))
+    }
+
+    pub fn replace(&self, (cache, snapshot): (Cache, Snapshot)) {
+        let mut current = self.loaded.write().unwrap();
+        let generation = current.as_ref().map_or(0, |loaded| loaded.generation + 1);
+        *current = Some(Loaded { generation, cache: Arc::new(cache), snapshot: Arc::new(snapshot) });
+        drop(current);
+        self.responses.invalidate(generation);
+    }
+}
+
+pub fn load_cache(path: &Path) -> Result<(Cache, Snapshot), String> {
+    let name = path.to_str().ok_or(format!("Invalid database path {:?}", path))?;
+    let start = Instant::now();
+    let metadata = std::fs::metadata(path).map_err(|e| format!("Reading {} failed: {}", name, e))?;
+    let cache = upcache::cache::load(name).map_err(|e| format!("Loading {} failed: {}", name, e))?;
+    metrics::CACHE_LOAD_DURATION.set(start.elapsed().as_secs_f64());
+    let snapshot = Snapshot::new(&cache, &metadata);
+    Ok((cache, snapshot))
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Starts a background thread loading the database, and then reloading it according
+/// to the reload policy. Failure of the initial load terminates the process.
+pub fn spawn_loader(state: Arc<State>, db_path: PathBuf, reload: Reload) {
+    std::thread::spawn(move || {
+        let mut last_modified = modified(&db_path);
+        match load_cache(&db_path) {
+            Ok(cache) => state.replace(cache),
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        info!("Loaded cache");
+
+        if reload.policy == ReloadPolicy::Never {
+            return;
+        }
+
+        loop {
+            std::thread::sleep(reload.interval());
+
+            let current = modified(&db_path);
+            if reload.policy == ReloadPolicy::OnChange && current == last_modified {
+                continue;
+            }
+
+            info!("Reloading cache from {}", db_path.display());
+            match load_cache(&db_path) {
+                Ok(cache) => {
+                    state.replace(cache);
+                    last_modified = current;
+                    metrics::RELOADS.with_label_values(&["success"]).inc();
+                    info!("Reloaded cache");
+                }
+                Err(e) => {
+                    metrics::RELOADS.with_label_values(&["failure"]).inc();
+                    error!("Reloading cache failed: {}", e);
+                }
+            }
+        }
+    });
+}
+
+/// State serving the test database of upcache, loaded the same way as in production
+#[cfg(test)]
+pub fn test_state() -> Arc<State> {
+    let path = upcache::testing::temp_path("vmaas.db");
+    upcache::testing::write_db(&path).unwrap();
+    let state = State::new(1 << 20);
+    state.replace(load_cache(&path).unwrap());
+    std::fs::remove_file(&path).unwrap();
+    Arc::new(state)
+}
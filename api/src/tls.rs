@@ -0,0 +1,114 @@
+//! TLS termination with rustls.
+//!
+//! The certificate and key are checked for modification periodically and swapped in for new
+//! handshakes without restarting the listeners. A failed reload keeps the previous pair.
+
+use log::{error, info};
+use rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use rustls::sign::{self, CertifiedKey};
+use rustls::{
+    AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, NoClientAuth, ResolvesServerCert,
+    RootCertStore, ServerConfig, SignatureScheme,
+};
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+use crate::config::Tls;
+
+fn open(path: &Path) -> Result<BufReader<File>, String> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| format!("Could not open {}: {}", path.display(), e))
+}
+
+fn load_certified_key(cert: &Path, key: &Path) -> Result<CertifiedKey, String> {
+    let chain = certs(&mut open(cert)?).map_err(|_| format!("Invalid certificate {}", cert.display()))?;
+    if chain.is_empty() {
+        return Err(format!("No certificate in {}", cert.display()));
+    }
+
+    let mut keys = pkcs8_private_keys(&mut open(key)?).map_err(|_| format!("Invalid key {}", key.display()))?;
+    if keys.is_empty() {
+        keys = rsa_private_keys(&mut open(key)?).map_err(|_| format!("Invalid key {}", key.display()))?;
+    }
+    let key_der = keys.first().ok_or(format!("No private key in {}", key.display()))?;
+    let signing_key = sign::any_supported_type(key_der).map_err(|_| format!("Unsupported key type in {}", key.display()))?;
+
+    Ok(CertifiedKey::new(chain, Arc::new(signing_key)))
+}
+
+fn load_roots(path: &Path) -> Result<RootCertStore, String> {
+    let mut roots = RootCertStore::empty();
+    for cert in certs(&mut open(path)?).map_err(|_| format!("Invalid certificate {}", path.display()))? {
+        roots.add(&cert).map_err(|e| format!("Invalid CA certificate in {}: {}", path.display(), e))?;
+    }
+    if roots.is_empty() {
+        return Err(format!("No CA certificate in {}", path.display()));
+    }
+    Ok(roots)
+}
+
+/// Serves the most recently loaded certificate regardless of SNI
+struct ReloadingResolver {
+    current: RwLock<CertifiedKey>,
+}
+
+impl ResolvesServerCert for ReloadingResolver {
+    fn resolve(&self, _: Option<webpki::DNSNameRef>, _: &[SignatureScheme]) -> Option<CertifiedKey> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+fn modified(tls: &Tls) -> (Option<SystemTime>, Option<SystemTime>) {
+    let mtime = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    (mtime(&tls.cert), mtime(&tls.key))
+}
+
+fn spawn_reloader(resolver: Arc<ReloadingResolver>, tls: Tls) {
+    std::thread::spawn(move || {
+        let mut last_modified = modified(&tls);
+        loop {
+            std::thread::sleep(tls.reload_interval());
+
+            let current = modified(&tls);
+            if current == last_modified {
+                continue;
+            }
+            match load_certified_key(&tls.cert, &tls.key) {
+                Ok(key) => {
+                    *resolver.current.write().unwrap() = key;
+                    last_modified = current;
+                    info!("Reloaded TLS certificate {}", tls.cert.display());
+                }
+                // Retried on the next check, the files may be in the middle of an update
+                Err(e) => error!("Reloading TLS certificate failed: {}", e),
+            }
+        }
+    });
+}
+
+/// Builds the rustls configuration shared by all TLS listeners and starts watching
+/// the certificate for changes
+pub fn server_config(tls: &Tls) -> Result<ServerConfig, String> {
+    let verifier = match tls.client_ca {
+        Some(ref ca) if tls.require_client_cert => AllowAnyAuthenticatedClient::new(load_roots(ca)?),
+        Some(ref ca) => AllowAnyAnonymousOrAuthenticatedClient::new(load_roots(ca)?),
+        None => NoClientAuth::new(),
+    };
+
+    let resolver = Arc::new(ReloadingResolver {
+        current: RwLock::new(load_certified_key(&tls.cert, &tls.key)?),
+    });
+    let mut config = ServerConfig::new(verifier);
+    config.cert_resolver = resolver.clone();
+    config.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+
+    if tls.reload_interval > 0 {
+        spawn_reloader(resolver, tls.clone());
+    }
+    Ok(config)
+}
@@ -0,0 +1,31 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use upcache::calc::updates::{Request, Updates};
+
+/// Benchmarks the updates calculation for the package list in `data.json`, once
+/// restricted to a single thread and once using the whole thread pool.
+/// The database is taken from `VMAAS_DB`, defaulting to `./vmaas.db`.
+fn updates(c: &mut Criterion) {
+    let db = std::env::var("VMAAS_DB").unwrap_or("./vmaas.db".to_string());
+    let cache = upcache::cache::load(&db).unwrap();
+
+    let package_list: Vec<String> = json::from_str(include_str!("../src/data.json")).unwrap();
+    let request = Request {
+        package_list,
+        ..Default::default()
+    };
+
+    let single = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+
+    let mut group = c.benchmark_group("calc_updates");
+    group.sample_size(10);
+    group.bench_function("sequential", |b| {
+        b.iter(|| single.install(|| Updates::calc_updates(&cache, request.clone()).unwrap()))
+    });
+    group.bench_function("parallel", |b| {
+        b.iter(|| Updates::calc_updates(&cache, request.clone()).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, updates);
+criterion_main!(benches);
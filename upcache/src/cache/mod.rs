@@ -9,6 +9,9 @@ use std::fmt::Display;
 mod util;
 
 use util::*;
+use crate::matching::NameIndex;
+use crate::cvss::{CvssError, CvssV2, CvssV3};
+use crate::timestamp;
 use env_logger::builder;
 
 #[derive(Debug, Deserialize, Serialize, Clone, Hash, PartialOrd, PartialEq, Eq, Ord)]
@@ -86,12 +89,17 @@ pub struct Cve {
     pub cvss3_metrics: Option<String>,
 
     pub impact: String,
-    pub published_date: Option<String>,
-    pub modified_date: Option<String>,
+    pub published_date: Option<Timestamp>,
+    pub modified_date: Option<Timestamp>,
     pub iava: Option<String>,
     pub description: Option<String>,
     pub cvss2_score: Option<f64>,
     pub cvss2_metrics: Option<String>,
+    /// Vectors parsed on load, so they are not parsed again by every request
+    #[serde(skip)]
+    pub cvss3_vector: Option<Result<CvssV3, CvssError>>,
+    #[serde(skip)]
+    pub cvss2_vector: Option<Result<CvssV2, CvssError>>,
 
     pub cve_source: String,
 
@@ -109,8 +117,7 @@ pub struct Repo {
     pub releasever: Option<String>,
     pub product: Option<String>,
     pub product_id: Option<i64>,
-    // DateTime, fix parsing
-    pub revision: Option<String>,
+    pub revision: Option<Timestamp>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Hash)]
@@ -123,8 +130,8 @@ pub struct Erratum {
     pub severity: String,
     pub description: Option<String>,
     pub solution: String,
-    pub issued: String,
-    pub updated: String,
+    pub issued: Option<Timestamp>,
+    pub updated: Option<Timestamp>,
     pub url: String,
 
     pub bugzillas: Vec<String>,
@@ -157,17 +164,42 @@ pub struct Cache {
     pub pkgid_to_repoids: Map<i64, Vec<i64>>,
 
     pub errataid_to_name: Map<i64, String>,
+    pub errataname_to_id: Map<String, i64>,
+    pub errataid_to_pkgids: Map<i64, Vec<i64>>,
+    pub errataid_to_cveids: Map<i64, Vec<i64>>,
     pub pkgid_to_errataids: Map<i64, Vec<i64>>,
     pub errataid_to_repoids: Map<i64, Vec<i64>>,
     pub cve_detail: Map<i64, Cve>,
-    pub dbchange: Map<String, String>,
+    pub cvename_to_id: Map<String, i64>,
+    pub dbchange: Map<String, Timestamp>,
     pub errata_detail: Map<i64, Erratum>,
     pub pkgerrata_to_module: Map<(i64, i64), Vec<i64>>,
-    pub modulename_to_id: Map<(String, String), i64>,
+    pub modulename_to_id: Map<(String, String), Vec<i64>>,
+    pub pkgid_to_module_ids: Map<i64, Vec<i64>>,
     pub src_pkg_id_to_pkg_ids: Map<i64, Vec<i64>>,
     pub strings: Map<i64, Option<String>>,
+    /// `user_version` of the exported database
+    pub schema_version: i64,
+    /// Number of timestamps which could not be parsed, and were left unset
+    pub malformed_timestamps: usize,
+
+    pub repolabel_index: NameIndex,
+    pub cvename_index: NameIndex,
+    pub erratumname_index: NameIndex,
 }
 
+/// Parses a timestamp column, malformed values are logged and counted, and left unset
+fn load_timestamp(r: &rusqlite::Row, idx: usize, column: &str, malformed: &mut usize) -> Result<Option<Timestamp>> {
+    let value: Option<String> = r.get(idx)?;
+    Ok(value.and_then(|value| match timestamp::parse(&value) {
+        Ok(ts) => Some(ts),
+        Err(e) => {
+            warn!("{}: {}", column, e);
+            *malformed += 1;
+            None
+        }
+    }))
+}
 
 pub fn load_updates(db: &mut Connection, cache: &mut Cache) -> Result<()> {
     load_rows(db, "updates", "name_id, package_id, package_order", "package_order", |r| {
@@ -251,7 +283,7 @@ pub fn load_repos(db: &mut Connection, cache: &mut Cache) -> Result<()> {
             releasever: r.get(5)?,
             product: r.get(6)?,
             product_id: r.get(7)?,
-            revision: r.get(8)?,
+            revision: load_timestamp(r, 8, "repo_detail.revision", &mut cache.malformed_timestamps)?,
         };
         cache.repo_detail.insert(r.get(0)?, repo);
         Ok(())
@@ -269,22 +301,26 @@ pub fn load_repos(db: &mut Connection, cache: &mut Cache) -> Result<()> {
 
 pub fn load_cve(db: &mut Connection, cache: &mut Cache) -> Result<()> {
     load_rows(db, "cve_detail", "id, name, redhat_url, secondary_url, cvss3_score, cvss3_metrics, impact, published_date, modified_date, iava, description, cvss2_score, cvss2_metrics, source", "id", |r| {
+        let cvss3_metrics: Option<String> = r.get(5)?;
+        let cvss2_metrics: Option<String> = r.get(12)?;
         let repo = Cve {
             name: r.get(1)?,
             redhat_url: r.get(2)?,
             secondary_url: r.get(3)?,
 
             cvss3_score: r.get(4)?,
-            cvss3_metrics: r.get(5)?,
+            cvss3_vector: cvss3_metrics.as_ref().map(|v| v.parse()),
+            cvss3_metrics,
 
             impact: r.get(6)?,
-            published_date: r.get(7)?,
-            modified_date: r.get(8)?,
+            published_date: load_timestamp(r, 7, "cve_detail.published_date", &mut cache.malformed_timestamps)?,
+            modified_date: load_timestamp(r, 8, "cve_detail.modified_date", &mut cache.malformed_timestamps)?,
 
             iava: r.get(9)?,
             description: r.get(10)?,
             cvss2_score: r.get(11)?,
-            cvss2_metrics: r.get(12)?,
+            cvss2_vector: cvss2_metrics.as_ref().map(|v| v.parse()),
+            cvss2_metrics,
             cve_source: r.get(13)?,
 
             cwes: vec![],
@@ -313,16 +349,24 @@ pub fn load_cve(db: &mut Connection, cache: &mut Cache) -> Result<()> {
         }
     }
 
+    for (cve_id, cve) in cache.cve_detail.iter() {
+        cache.cvename_to_id.insert(cve.name.clone(), *cve_id);
+        for errata_id in cve.errataids.iter() {
+            cache.errataid_to_cveids.entry(*errata_id).or_default().push(*cve_id);
+        }
+    }
+
     Ok(())
 }
 
 pub fn load_dbchange(db: &mut Connection, cache: &mut Cache) -> Result<()> {
-    load_rows(db, "dbchange", "errata_changes, cve_changes, repository_changes, last_change, exported", "exported", |row| {
-        cache.dbchange.insert("errata_changes".to_string(), row.get(0)?);
-        cache.dbchange.insert("cve_changes".to_string(), row.get(1)?);
-        cache.dbchange.insert("repository_changes".to_string(), row.get(2)?);
-        cache.dbchange.insert("last_change".to_string(), row.get(3)?);
-        cache.dbchange.insert("exported".to_string(), row.get(4)?);
+    let keys = ["errata_changes", "cve_changes", "repository_changes", "last_change", "exported"];
+    load_rows(db, "dbchange", &keys.join(", "), "exported", |row| {
+        for (idx, key) in keys.iter().enumerate() {
+            if let Some(ts) = load_timestamp(row, idx, &format!("dbchange.{}", key), &mut cache.malformed_timestamps)? {
+                cache.dbchange.insert(key.to_string(), ts);
+            }
+        }
         Ok(())
     })?;
     Ok(())
@@ -338,8 +382,8 @@ pub fn load_errata(db: &mut Connection, cache: &mut Cache) -> Result<()> {
             severity: r.get(5)?,
             description: r.get(6)?,
             solution: r.get(7)?,
-            issued: r.get(8)?,
-            updated: r.get(9)?,
+            issued: load_timestamp(r, 8, "errata_detail.issued", &mut cache.malformed_timestamps)?,
+            updated: load_timestamp(r, 9, "errata_detail.updated", &mut cache.malformed_timestamps)?,
             url: r.get(10)?,
 
             bugzillas: vec![],
@@ -366,7 +410,7 @@ pub fn load_errata(db: &mut Connection, cache: &mut Cache) -> Result<()> {
 
 pub fn load_modules(db: &mut Connection, cache: &mut Cache) -> Result<()> {
     load_rows(db, "module_stream", "module, stream, stream_id", "stream_id", |r| {
-        cache.modulename_to_id.insert((r.get(0)?, r.get(1)?), r.get(2)?);
+        cache.modulename_to_id.entry((r.get(0)?, r.get(1)?)).or_default().push(r.get(2)?);
         Ok(())
     })?;
     load_rows(db, "errata_modulepkg", "pkg_id, errata_id, module_stream_id", "pkg_id", |r| {
@@ -376,6 +420,15 @@ pub fn load_modules(db: &mut Connection, cache: &mut Cache) -> Result<()> {
         Ok(())
     })?;
 
+    for ((pkg_id, _), module_ids) in cache.pkgerrata_to_module.iter() {
+        let pkg_modules = cache.pkgid_to_module_ids.entry(*pkg_id).or_default();
+        for module_id in module_ids {
+            if !pkg_modules.contains(module_id) {
+                pkg_modules.push(*module_id);
+            }
+        }
+    }
+
     Ok(())
 }
 
@@ -391,7 +444,7 @@ pub fn load_names(db: &mut Connection, cache: &mut Cache) -> Result<()> {
 #[no_mangle]
 pub extern "C" fn load(name: &str) -> Result<Cache> {
     let file = PathBuf::from(name);
-    let mut db = Connection::open_with_flags(&file, OpenFlags::SQLITE_OPEN_READ_ONLY).expect("Opening failed");
+    let mut db = Connection::open_with_flags(&file, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
 
     let mut cache = Cache::default();
 
@@ -404,7 +457,9 @@ pub extern "C" fn load(name: &str) -> Result<Cache> {
 
     cache.pkgid_to_repoids = load_multimap(&mut db, "pkg_repo", "pkg_id", "repo_id", "pkg_id")?;
     cache.errataid_to_name = load_map(&mut db, "errata_detail", "id", "name", "id")?;
+    cache.errataname_to_id = invert(&cache.errataid_to_name);
     cache.pkgid_to_errataids = load_multimap(&mut db, "pkg_errata", "pkg_id", "errata_id", "pkg_id")?;
+    cache.errataid_to_pkgids = load_multimap(&mut db, "pkg_errata", "errata_id", "pkg_id", "errata_id")?;
     cache.errataid_to_repoids = load_multimap(&mut db, "errata_repo", "errata_id", "repo_id", "errata_id")?;
 
     load_cve(&mut db, &mut cache)?;
@@ -413,7 +468,15 @@ pub extern "C" fn load(name: &str) -> Result<Cache> {
     load_modules(&mut db, &mut cache)?;
 
     cache.strings = load_map::<i64, _>(&mut db, "string", "id", "string", "id")?;
+    cache.schema_version = db.query_row("PRAGMA user_version", params![], |r| r.get(0))?;
 
+    cache.repolabel_index = NameIndex::new(cache.repolabel_to_ids.keys().cloned());
+    cache.cvename_index = NameIndex::new(cache.cvename_to_id.keys().cloned());
+    cache.erratumname_index = NameIndex::new(cache.errataname_to_id.keys().cloned());
+
+    if cache.malformed_timestamps > 0 {
+        warn!("{} malformed timestamps were left unset", cache.malformed_timestamps);
+    }
     println!("Loaded all");
     Ok(cache)
 }
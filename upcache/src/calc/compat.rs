@@ -0,0 +1,216 @@
+//! Response formats of the older API versions, built from the current `updates::Response`
+
+use crate::prelude::*;
+use crate::calc::updates;
+use std::collections::BTreeSet;
+
+pub mod v1 {
+    use super::*;
+
+    /// Updates are listed without the repository they come from, so every
+    /// package and erratum pair is listed just once
+    #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, JsonSchema)]
+    #[schemars(rename = "PkgUpdateV1")]
+    pub struct PkgUpdate {
+        pub package: Nevra,
+        pub erratum: String,
+    }
+
+    #[derive(Debug, Clone, Serialize, JsonSchema, Default)]
+    #[schemars(rename = "UpdatesPkgDetailV1")]
+    pub struct UpdatesPkgDetail {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub summary: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub description: Option<String>,
+
+        pub available_updates: Vec<PkgUpdate>,
+    }
+
+    #[derive(Debug, Clone, Serialize, JsonSchema, Default)]
+    #[schemars(rename = "ResponseV1")]
+    pub struct Response {
+        pub update_list: Map<String, UpdatesPkgDetail>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub repository_list: Option<Vec<String>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub releasever: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub basearch: Option<String>,
+    }
+
+    impl From<&updates::Response> for Response {
+        fn from(res: &updates::Response) -> Self {
+            let update_list = res.update_list.iter().map(|(pkg, detail)| {
+                let available_updates = detail.available_updates.iter()
+                    .map(|u| PkgUpdate {
+                        package: u.package.clone(),
+                        erratum: u.erratum.clone(),
+                    })
+                    .collect::<BTreeSet<_>>();
+
+                (pkg.clone(), UpdatesPkgDetail {
+                    summary: detail.summary.clone(),
+                    description: detail.description.clone(),
+                    available_updates: available_updates.into_iter().collect(),
+                })
+            }).collect();
+
+            Response {
+                update_list,
+                repository_list: res.repository_list.clone(),
+                releasever: res.releasever.clone(),
+                basearch: res.basearch.clone(),
+            }
+        }
+    }
+}
+
+pub mod v2 {
+    use super::*;
+
+    /// Same as the current format, except that `releasever` is omitted
+    /// instead of being empty for repositories without one
+    #[derive(Debug, Clone, Serialize, JsonSchema)]
+    #[schemars(rename = "PkgUpdateV2")]
+    pub struct PkgUpdate {
+        pub package: Nevra,
+        pub erratum: String,
+        pub repository: Option<String>,
+        pub basearch: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub releasever: Option<String>,
+    }
+
+    #[derive(Debug, Clone, Serialize, JsonSchema, Default)]
+    #[schemars(rename = "UpdatesPkgDetailV2")]
+    pub struct UpdatesPkgDetail {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub summary: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub description: Option<String>,
+
+        pub available_updates: Vec<PkgUpdate>,
+    }
+
+    #[derive(Debug, Clone, Serialize, JsonSchema, Default)]
+    #[schemars(rename = "ResponseV2")]
+    pub struct Response {
+        pub update_list: Map<String, UpdatesPkgDetail>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub repository_list: Option<Vec<String>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub modules_list: Option<Vec<updates::ModuleSpec>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub releasever: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub basearch: Option<String>,
+    }
+
+    impl From<&updates::Response> for Response {
+        fn from(res: &updates::Response) -> Self {
+            let update_list = res.update_list.iter().map(|(pkg, detail)| {
+                let available_updates = detail.available_updates.iter()
+                    .map(|u| PkgUpdate {
+                        package: u.package.clone(),
+                        erratum: u.erratum.clone(),
+                        repository: u.repository.clone(),
+                        basearch: u.basearch.clone(),
+                        releasever: Some(u.releasever.clone()).filter(|r| !r.is_empty()),
+                    })
+                    .collect();
+
+                (pkg.clone(), UpdatesPkgDetail {
+                    summary: detail.summary.clone(),
+                    description: detail.description.clone(),
+                    available_updates,
+                })
+            }).collect();
+
+            Response {
+                update_list,
+                repository_list: res.repository_list.clone(),
+                modules_list: res.modules_list.clone(),
+                releasever: res.releasever.clone(),
+                basearch: res.basearch.clone(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calc::updates::Updates;
+    use crate::testing::CACHE;
+
+    /// kernel-1.2 is offered from both repositories
+    fn response() -> updates::Response {
+        let req = updates::Request {
+            package_list: vec!["kernel-1.0-1.el7.x86_64".to_string()],
+            repository_list: Some(vec!["rhel-7-server-rpms".to_string(), "rhel-7-server-extras-rpms".to_string()]),
+            ..Default::default()
+        };
+        Updates::calc_updates(&CACHE, req).unwrap()
+    }
+
+    #[test]
+    fn v1_drops_repository_and_dedups() {
+        let res = v1::Response::from(&response());
+        assert_eq!(json::to_value(&res).unwrap(), json::json!({
+            "update_list": {
+                "kernel-1.0-1.el7.x86_64": {
+                    "available_updates": [
+                        {"package": "kernel-1.1-1.el7.x86_64", "erratum": "RHSA-2020:0001"},
+                        {"package": "kernel-1.2-1.el7.x86_64", "erratum": "RHBA-2020:0002"},
+                    ],
+                },
+            },
+            "repository_list": ["rhel-7-server-rpms", "rhel-7-server-extras-rpms"],
+        }));
+    }
+
+    #[test]
+    fn v2_omits_empty_releasever() {
+        let mut res = response();
+        let pkg = res.update_list.get_mut("kernel-1.0-1.el7.x86_64").unwrap();
+        pkg.available_updates.sort_by(|a, b| (&a.package, &a.repository).cmp(&(&b.package, &b.repository)));
+        // Repositories without a releasever have it empty in the current format
+        for update in &mut pkg.available_updates {
+            if update.repository.as_deref() == Some("rhel-7-server-extras-rpms") {
+                update.releasever.clear();
+            }
+        }
+
+        let res = v2::Response::from(&res);
+        assert_eq!(json::to_value(&res).unwrap(), json::json!({
+            "update_list": {
+                "kernel-1.0-1.el7.x86_64": {
+                    "available_updates": [
+                        {
+                            "package": "kernel-1.1-1.el7.x86_64",
+                            "erratum": "RHSA-2020:0001",
+                            "repository": "rhel-7-server-rpms",
+                            "basearch": "x86_64",
+                            "releasever": "7Server",
+                        },
+                        {
+                            "package": "kernel-1.2-1.el7.x86_64",
+                            "erratum": "RHBA-2020:0002",
+                            "repository": "rhel-7-server-extras-rpms",
+                            "basearch": "x86_64",
+                        },
+                        {
+                            "package": "kernel-1.2-1.el7.x86_64",
+                            "erratum": "RHBA-2020:0002",
+                            "repository": "rhel-7-server-rpms",
+                            "basearch": "x86_64",
+                            "releasever": "7Server",
+                        },
+                    ],
+                },
+            },
+            "repository_list": ["rhel-7-server-rpms", "rhel-7-server-extras-rpms"],
+        }));
+    }
+}
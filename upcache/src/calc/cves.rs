@@ -0,0 +1,298 @@
+use crate::prelude::*;
+use crate::Cache;
+use crate::calc::updates::Updates;
+use crate::cache::Cve;
+use crate::timestamp;
+use crate::cvss::{AttackVector, CvssError, CvssV2, CvssV3, Vector};
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+pub struct Request {
+    /// CVE names, a single entry is treated as a regular expression
+    pub cve_list: Vec<String>,
+    /// Return only CVEs modified after this timestamp
+    #[serde(default, deserialize_with = "timestamp::deserialize_opt")]
+    pub modified_since: Option<Timestamp>,
+    /// Return only CVEs modified before this timestamp
+    #[serde(default, deserialize_with = "timestamp::deserialize_opt")]
+    pub modified_until: Option<Timestamp>,
+    /// Return only CVEs published after this timestamp
+    #[serde(default, deserialize_with = "timestamp::deserialize_opt")]
+    pub published_since: Option<Timestamp>,
+    /// Return only CVEs published before this timestamp
+    #[serde(default, deserialize_with = "timestamp::deserialize_opt")]
+    pub published_until: Option<Timestamp>,
+    /// Return only CVEs with at least one of these CWE IDs, e.g. `CWE-79`
+    pub cwe_list: Option<Vec<String>>,
+    /// Return only CVEs with one of these impact levels, e.g. `Important`
+    pub impact_list: Option<Vec<String>>,
+    /// Return only CVEs with a CVSS base score of at least this, v3 when the CVE has one,
+    /// v2 otherwise
+    pub cvss_from: Option<f64>,
+    /// Return only CVEs with a CVSS base score of at most this
+    pub cvss_to: Option<f64>,
+    /// Return only CVEs exploitable through one of these attack vectors, taken from the CVSS v3
+    /// vector, or the v2 one when missing
+    pub attack_vector_list: Option<Vec<AttackVector>>,
+    #[serde(flatten)]
+    pub paging: PagingInfo,
+}
+
+#[derive(Debug, Serialize, JsonSchema, Default)]
+pub struct CveDetail {
+    pub synopsis: String,
+    pub description: Option<String>,
+    pub impact: String,
+    pub public_date: Option<Timestamp>,
+    pub modified_date: Option<Timestamp>,
+    pub cwe_list: Vec<String>,
+    pub redhat_url: Option<String>,
+    pub secondary_url: Option<String>,
+    pub cvss3_score: Option<f64>,
+    pub cvss3_metrics: Option<String>,
+    pub cvss2_score: Option<f64>,
+    pub cvss2_metrics: Option<String>,
+    /// Parsed `cvss3_metrics`, missing when unset or malformed
+    pub cvss3_vector: Option<CvssV3>,
+    /// Parsed `cvss2_metrics`, missing when unset or malformed
+    pub cvss2_vector: Option<CvssV2>,
+    /// Malformed vectors and stored scores which do not match their vectors
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub cvss_errors: Vec<String>,
+    pub package_list: Vec<Nevra>,
+    pub errata_list: Vec<String>,
+    pub source: String,
+}
+
+#[derive(Debug, Serialize, JsonSchema, Default)]
+pub struct Response {
+    pub cve_list: BTreeMap<String, CveDetail>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified_since: Option<Timestamp>,
+    #[serde(flatten)]
+    pub page: Page,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterError {
+    EmptyRange(&'static str),
+    InvalidScore(f64),
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FilterError::EmptyRange(field) => write!(f, "Lower bound of {} is above its upper bound", field),
+            FilterError::InvalidScore(score) => write!(f, "CVSS score {} is out of the 0 to 10 range", score),
+        }
+    }
+}
+
+impl Error for FilterError {}
+
+/// Whether an optional date falls into the window, CVEs without the date pass only when
+/// the window is unbounded, the same as CVEs without a score and the score range
+fn within(date: Option<Timestamp>, since: Option<Timestamp>, until: Option<Timestamp>) -> bool {
+    match date {
+        Some(date) => !matches!(since, Some(since) if date < since) && !matches!(until, Some(until) if date > until),
+        None => since.is_none() && until.is_none(),
+    }
+}
+
+fn contains_ignore_case(list: &[String], value: &str) -> bool {
+    list.iter().any(|v| v.eq_ignore_ascii_case(value))
+}
+
+impl Request {
+    fn validate(&self) -> Result<(), FilterError> {
+        for score in self.cvss_from.iter().chain(self.cvss_to.iter()) {
+            if !(0.0..=10.0).contains(score) {
+                return Err(FilterError::InvalidScore(*score));
+            }
+        }
+        if matches!((self.cvss_from, self.cvss_to), (Some(from), Some(to)) if from > to) {
+            return Err(FilterError::EmptyRange("cvss"));
+        }
+        if matches!((self.modified_since, self.modified_until), (Some(since), Some(until)) if since > until) {
+            return Err(FilterError::EmptyRange("modified"));
+        }
+        if matches!((self.published_since, self.published_until), (Some(since), Some(until)) if since > until) {
+            return Err(FilterError::EmptyRange("published"));
+        }
+        Ok(())
+    }
+
+    /// Whether the CVE passes all the filters of the request, besides its name
+    fn matches(&self, cve: &Cve) -> bool {
+        if !within(cve.modified_date, self.modified_since, self.modified_until)
+            || !within(cve.published_date, self.published_since, self.published_until) {
+            return false;
+        }
+        if let Some(ref cwes) = self.cwe_list {
+            if !cve.cwes.iter().any(|cwe| contains_ignore_case(cwes, cwe)) {
+                return false;
+            }
+        }
+        if let Some(ref impacts) = self.impact_list {
+            if !contains_ignore_case(impacts, &cve.impact) {
+                return false;
+            }
+        }
+        if self.cvss_from.is_some() || self.cvss_to.is_some() {
+            let score = match cve.cvss3_score.or(cve.cvss2_score) {
+                Some(score) => score,
+                None => return false,
+            };
+            if matches!(self.cvss_from, Some(from) if score < from) || matches!(self.cvss_to, Some(to) if score > to) {
+                return false;
+            }
+        }
+        if let Some(ref vectors) = self.attack_vector_list {
+            if !matches!(attack_vector(cve), Some(av) if vectors.contains(&av)) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Attack vector of the CVE, preferring the CVSS v3 vector
+fn attack_vector(cve: &Cve) -> Option<AttackVector> {
+    match (&cve.cvss3_vector, &cve.cvss2_vector) {
+        (Some(Ok(v3)), _) => Some(v3.attack_vector),
+        (_, Some(Ok(v2))) => Some(v2.access_vector),
+        _ => None,
+    }
+}
+
+/// Checks the stored score against the parsed vector, problems are collected in `errors`,
+/// prefixed with the name of the field
+fn checked_vector<V: Vector + Clone>(vector: &Option<Result<V, CvssError>>, score: Option<f64>, field: &str, errors: &mut Vec<String>) -> Option<V> {
+    let vector = match vector {
+        Some(Ok(vector)) => vector,
+        Some(Err(e)) => {
+            errors.push(format!("{}: {}", field, e));
+            return None;
+        }
+        None => return None,
+    };
+    if let Some(Err(e)) = score.map(|score| vector.validate(score)) {
+        errors.push(format!("{}: {}", field, e));
+    }
+    Some(vector.clone())
+}
+
+pub fn get_cves(cache: &Cache, req: Request) -> Result<Response> {
+    let mut res = Response::default();
+    req.validate()?;
+
+    let mut cves = req.cve_list.clone();
+    if cves.len() == 1 {
+        cves = cache.cvename_index.matching(&cves[0])?;
+    }
+
+    let cves = cves.into_iter()
+        .filter_map(|name| {
+            let cve = cache.cvename_to_id.get(&name).and_then(|id| cache.cve_detail.get(id))?;
+            Some(name).filter(|_| req.matches(cve))
+        })
+        .collect();
+
+    let (cves, page) = req.paging.paginate(cves);
+    for name in cves {
+        let cve = &cache.cve_detail[&cache.cvename_to_id[&name]];
+        let mut cvss_errors = vec![];
+        let cvss3_vector = checked_vector(&cve.cvss3_vector, cve.cvss3_score, "cvss3", &mut cvss_errors);
+        let cvss2_vector = checked_vector(&cve.cvss2_vector, cve.cvss2_score, "cvss2", &mut cvss_errors);
+        res.cve_list.insert(name, CveDetail {
+            synopsis: cve.name.clone(),
+            description: cve.description.clone(),
+            impact: cve.impact.clone(),
+            public_date: cve.published_date,
+            modified_date: cve.modified_date,
+            cwe_list: cve.cwes.clone(),
+            redhat_url: cve.redhat_url.clone(),
+            secondary_url: cve.secondary_url.clone(),
+            cvss3_score: cve.cvss3_score,
+            cvss3_metrics: cve.cvss3_metrics.clone(),
+            cvss2_score: cve.cvss2_score,
+            cvss2_metrics: cve.cvss2_metrics.clone(),
+            cvss3_vector,
+            cvss2_vector,
+            cvss_errors,
+            package_list: cve.pkgids.iter().map(|id| Updates::build_nevra(cache, *id)).collect(),
+            errata_list: cve.errataids.iter().filter_map(|id| cache.errataid_to_name.get(id).cloned()).collect(),
+            source: cve.cve_source.clone(),
+        });
+    }
+    res.modified_since = req.modified_since;
+    res.page = page;
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::CACHE;
+
+    fn request() -> Request {
+        Request {
+            cve_list: vec!["CVE-2020-.*".to_string()],
+            ..Default::default()
+        }
+    }
+
+    fn names(req: Request) -> Vec<String> {
+        get_cves(&CACHE, req).unwrap().cve_list.into_keys().collect()
+    }
+
+    fn ts(value: &str) -> Option<Timestamp> {
+        Some(timestamp::parse(value).unwrap())
+    }
+
+    #[test]
+    fn unfiltered() {
+        assert_eq!(names(request()), vec!["CVE-2020-0001", "CVE-2020-0002", "CVE-2020-0003", "CVE-2020-0004"]);
+    }
+
+    #[test]
+    fn dates_exclude_cves_without_them() {
+        let req = Request { modified_since: ts("2020-01-01"), ..request() };
+        assert_eq!(names(req), vec!["CVE-2020-0001", "CVE-2020-0004"]);
+
+        let req = Request { published_until: ts("2020-03-01"), ..request() };
+        assert_eq!(names(req), vec!["CVE-2020-0001", "CVE-2020-0002"]);
+
+        let req = Request { published_since: ts("2020-01-15"), published_until: ts("2020-02-01"), ..request() };
+        assert_eq!(names(req), vec!["CVE-2020-0002"]);
+    }
+
+    #[test]
+    fn scores_exclude_cves_without_them() {
+        let req = Request { cvss_from: Some(5.0), ..request() };
+        assert_eq!(names(req), vec!["CVE-2020-0001", "CVE-2020-0003"]);
+
+        let req = Request { cvss_to: Some(7.5), ..request() };
+        assert_eq!(names(req), vec!["CVE-2020-0001", "CVE-2020-0002"]);
+    }
+
+    #[test]
+    fn cwe_impact_and_attack_vector() {
+        let req = Request { cwe_list: Some(vec!["cwe-79".to_string()]), ..request() };
+        assert_eq!(names(req), vec!["CVE-2020-0001", "CVE-2020-0003"]);
+
+        let req = Request { impact_list: Some(vec!["low".to_string(), "Moderate".to_string()]), ..request() };
+        assert_eq!(names(req), vec!["CVE-2020-0002", "CVE-2020-0004"]);
+
+        let req = Request { attack_vector_list: Some(vec![AttackVector::Local]), ..request() };
+        assert_eq!(names(req), vec!["CVE-2020-0002"]);
+    }
+
+    #[test]
+    fn invalid_filters() {
+        let err = |req: Request| get_cves(&CACHE, req).unwrap_err().to_string();
+        assert_eq!(err(Request { cvss_from: Some(11.0), ..request() }), FilterError::InvalidScore(11.0).to_string());
+        assert_eq!(err(Request { cvss_from: Some(7.0), cvss_to: Some(5.0), ..request() }), FilterError::EmptyRange("cvss").to_string());
+        let req = Request { modified_since: ts("2020-02-01"), modified_until: ts("2020-01-01"), ..request() };
+        assert_eq!(err(req), FilterError::EmptyRange("modified").to_string());
+    }
+}
@@ -0,0 +1,23 @@
+use crate::prelude::*;
+use crate::Cache;
+
+/// Timestamps of the last changes of the data in the snapshot
+#[derive(Debug, Serialize, JsonSchema, Default)]
+pub struct Response {
+    pub errata_changes: Option<Timestamp>,
+    pub cve_changes: Option<Timestamp>,
+    pub repository_changes: Option<Timestamp>,
+    pub last_change: Option<Timestamp>,
+    pub exported: Option<Timestamp>,
+}
+
+pub fn get_dbchange(cache: &Cache) -> Response {
+    let get = |key: &str| cache.dbchange.get(key).cloned();
+    Response {
+        errata_changes: get("errata_changes"),
+        cve_changes: get("cve_changes"),
+        repository_changes: get("repository_changes"),
+        last_change: get("last_change"),
+        exported: get("exported"),
+    }
+}
@@ -0,0 +1,119 @@
+use crate::prelude::*;
+use crate::Cache;
+use crate::timestamp;
+use crate::calc::updates::Updates;
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct Request {
+    /// Erratum names, a single entry is treated as a regular expression
+    pub errata_list: Vec<String>,
+    /// Return only errata updated after this timestamp, errata without the date are left out
+    #[serde(default, deserialize_with = "timestamp::deserialize_opt")]
+    pub modified_since: Option<Timestamp>,
+    #[serde(flatten)]
+    pub paging: PagingInfo,
+}
+
+#[derive(Debug, Serialize, JsonSchema, Default)]
+pub struct ErratumDetail {
+    pub synopsis: String,
+    pub summary: String,
+    pub r#type: String,
+    pub severity: String,
+    pub description: Option<String>,
+    pub solution: String,
+    pub issued: Option<Timestamp>,
+    pub updated: Option<Timestamp>,
+    pub url: String,
+    pub cve_list: Vec<String>,
+    pub package_list: Vec<Nevra>,
+    pub bugzilla_list: Vec<String>,
+    pub reference_list: Vec<String>,
+}
+
+#[derive(Debug, Serialize, JsonSchema, Default)]
+pub struct Response {
+    pub errata_list: BTreeMap<String, ErratumDetail>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified_since: Option<Timestamp>,
+    #[serde(flatten)]
+    pub page: Page,
+}
+
+pub fn get_errata(cache: &Cache, req: Request) -> Result<Response> {
+    let mut res = Response::default();
+
+    let mut errata = req.errata_list.clone();
+    if errata.len() == 1 {
+        errata = cache.erratumname_index.matching(&errata[0])?;
+    }
+
+    let errata = errata.into_iter()
+        .filter_map(|name| {
+            let erratum = cache.errataname_to_id.get(&name).and_then(|id| cache.errata_detail.get(id))?;
+            if let Some(since) = req.modified_since {
+                if erratum.updated.is_none_or(|updated| updated < since) {
+                    return None;
+                }
+            }
+            Some(name)
+        })
+        .collect();
+
+    let (errata, page) = req.paging.paginate(errata);
+    for name in errata {
+        let id = cache.errataname_to_id[&name];
+        let erratum = &cache.errata_detail[&id];
+
+        let cve_list = cache.errataid_to_cveids.get(&id).into_iter().flatten()
+            .map(|cve_id| cache.cve_detail[cve_id].name.clone())
+            .collect();
+        let package_list = cache.errataid_to_pkgids.get(&id).into_iter().flatten()
+            .map(|pkg_id| Updates::build_nevra(cache, *pkg_id))
+            .collect();
+
+        res.errata_list.insert(name, ErratumDetail {
+            synopsis: erratum.synopsis.clone(),
+            summary: erratum.summary.clone(),
+            r#type: erratum.r#type.clone(),
+            severity: erratum.severity.clone(),
+            description: erratum.description.clone(),
+            solution: erratum.solution.clone(),
+            issued: erratum.issued,
+            updated: erratum.updated,
+            url: erratum.url.clone(),
+            cve_list,
+            package_list,
+            bugzilla_list: erratum.bugzillas.clone(),
+            reference_list: erratum.refs.clone(),
+        });
+    }
+    res.modified_since = req.modified_since;
+    res.page = page;
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn names(cache: &Cache, modified_since: Option<&str>) -> Vec<String> {
+        let req = Request {
+            errata_list: vec![".*".to_string()],
+            modified_since: modified_since.map(|value| timestamp::parse(value).unwrap()),
+            paging: PagingInfo::default(),
+        };
+        get_errata(cache, req).unwrap().errata_list.into_keys().collect()
+    }
+
+    #[test]
+    fn modified_since_excludes_errata_without_date() {
+        let mut cache = testing::load().unwrap();
+        assert_eq!(names(&cache, Some("2020-03-03")), vec!["OTHER-2020:0004", "RHBA-2020:0002"]);
+
+        cache.errata_detail.get_mut(&4).unwrap().updated = None;
+        assert_eq!(names(&cache, Some("2020-03-03")), vec!["RHBA-2020:0002"]);
+        assert_eq!(names(&cache, None).len(), 4);
+    }
+}
@@ -1,4 +1,9 @@
 use crate::prelude::*;
 
 pub mod updates;
-pub mod repos;
\ No newline at end of file
+pub mod repos;
+pub mod compat;
+pub mod cves;
+pub mod errata;
+pub mod vulnerabilities;
+pub mod dbchange;
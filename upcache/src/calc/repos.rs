@@ -1,37 +1,46 @@
 use crate::prelude::*;
 use crate::Cache;
+use crate::timestamp;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct Request {
+    /// Repository labels, a single entry is treated as a regular expression
     pub repository_list: Vec<String>,
-    pub modified_since: Option<String>,
+    /// Return only repositories revised after this timestamp, repositories without a revision are left out
+    #[serde(default, deserialize_with = "timestamp::deserialize_opt")]
+    pub modified_since: Option<Timestamp>,
+    #[serde(flatten)]
+    pub paging: PagingInfo,
 }
 
-#[derive(Debug, Serialize, Default)]
+#[derive(Debug, Serialize, JsonSchema, Default)]
 pub struct ResRepo {
     pub label: String,
     pub name: String,
     pub url: String,
     pub basearch: String,
     pub releasever: String,
-    pub product: Option<i32>,
-    pub revision: Option<String>,
+    pub product: Option<String>,
+    pub revision: Option<Timestamp>,
 }
 
-#[derive(Debug, Serialize, Default)]
+#[derive(Debug, Serialize, JsonSchema, Default)]
 pub struct Response {
-    pub repository_list: Map<String, ResRepo>,
+    /// Repositories by label, single label can be shared by multiple architectures and releases
+    pub repository_list: BTreeMap<String, Vec<ResRepo>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified_since: Option<Timestamp>,
+    #[serde(flatten)]
+    pub page: Page,
 }
 
-fn repos_by_regex(cache: &Cache, repo_regex: &str) -> Result<Vec<String>> {
-    let re = regex::Regex::from_str(repo_regex)?;
-    let mut res = vec![];
-    for (l, ids) in cache.repolabel_to_ids.iter() {
-        if re.is_match(&l) {
-            res.push(l.clone());
-        }
+fn modified_since(cache: &Cache, ids: &[i64], since: &Option<Timestamp>) -> bool {
+    match since {
+        Some(since) => ids.iter().any(|id| {
+            cache.repo_detail[id].revision.as_ref().is_some_and(|revision| revision >= since)
+        }),
+        None => true,
     }
-    Ok(res)
 }
 
 pub fn get_repos(cache: &Cache, req: Request) -> Result<Response> {
@@ -42,7 +51,57 @@ pub fn get_repos(cache: &Cache, req: Request) -> Result<Response> {
         return Ok(res);
     }
     if repos.len() == 1 {
-        repos = repos_by_regex(cache, &repos[0])?;
+        repos = cache.repolabel_index.matching(&repos[0])?;
     };
+
+    let repos = repos.into_iter()
+        .filter(|label| match cache.repolabel_to_ids.get(label) {
+            Some(ids) => modified_since(cache, ids, &req.modified_since),
+            None => false,
+        })
+        .collect();
+
+    let (repos, page) = req.paging.paginate(repos);
+    for label in repos {
+        let mut details = cache.repolabel_to_ids[&label].iter().map(|id| {
+            let repo = &cache.repo_detail[id];
+            ResRepo {
+                label: repo.label.clone(),
+                name: repo.name.clone(),
+                url: repo.url.clone(),
+                basearch: repo.basearch.clone().unwrap_or_default(),
+                releasever: repo.releasever.clone().unwrap_or_default(),
+                product: repo.product.clone(),
+                revision: repo.revision,
+            }
+        }).collect::<Vec<_>>();
+        details.sort_by(|a, b| (&a.basearch, &a.releasever, &a.url).cmp(&(&b.basearch, &b.releasever, &b.url)));
+        res.repository_list.insert(label, details);
+    }
+    res.modified_since = req.modified_since;
+    res.page = page;
     Ok(res)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::CACHE;
+
+    fn labels(modified_since: Option<&str>) -> Vec<String> {
+        let req = Request {
+            repository_list: vec!["rhel-7-server-rpms", "rhel-7-server-extras-rpms", "other-7-rpms"]
+                .into_iter().map(String::from).collect(),
+            modified_since: modified_since.map(|value| timestamp::parse(value).unwrap()),
+            paging: PagingInfo::default(),
+        };
+        get_repos(&CACHE, req).unwrap().repository_list.into_keys().collect()
+    }
+
+    #[test]
+    fn modified_since_excludes_repos_without_revision() {
+        assert_eq!(labels(None), vec!["other-7-rpms", "rhel-7-server-extras-rpms", "rhel-7-server-rpms"]);
+        assert_eq!(labels(Some("2020-01-01")), vec!["rhel-7-server-extras-rpms", "rhel-7-server-rpms"]);
+        assert_eq!(labels(Some("2020-01-15")), vec!["rhel-7-server-extras-rpms"]);
+    }
+}
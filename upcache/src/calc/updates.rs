@@ -2,16 +2,17 @@ use crate::prelude::*;
 
 use crate::cache::{Cache, NevraId};
 use std::collections::BTreeSet;
+use rayon::prelude::*;
 
 pub struct Updates;
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct ModuleSpec {
     pub module_name: String,
     pub module_stream: String,
 }
 
-#[derive(Debug, Default, Deserialize, Clone)]
+#[derive(Debug, Default, Deserialize, JsonSchema, Clone)]
 pub struct Request {
     pub package_list: Vec<String>,
 
@@ -19,40 +20,147 @@ pub struct Request {
     pub modules_list: Option<Vec<ModuleSpec>>,
     pub releasever: Option<String>,
     pub basearch: Option<String>,
+
+    /// Return only the newest applicable NEVRA per package, along with all errata it fixes
+    #[serde(default)]
+    pub latest_only: bool,
+
+    /// Attach the reasoning behind accepting or rejecting each update candidate
+    #[serde(default)]
+    pub explain: bool,
+}
+
+/// Update requests for multiple systems, keyed by system ID
+#[derive(Debug, Default, Deserialize, JsonSchema, Clone)]
+pub struct BatchRequest {
+    pub systems: BTreeMap<String, Request>,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema, Default)]
+pub struct BatchResponse {
+    pub systems: BTreeMap<String, Response>,
+    /// Systems whose updates could not be calculated, with the error
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub errors: BTreeMap<String, String>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, JsonSchema)]
 pub struct PkgUpdate {
-    package: Nevra,
-    erratum: String,
+    pub package: Nevra,
+    pub erratum: String,
 
-    repository: Option<String>,
-    basearch: Option<String>,
+    pub repository: Option<String>,
+    pub basearch: Option<String>,
     // TODO: Make this an option string
-    releasever: String,
+    pub releasever: String,
+
+    /// Errata fixed cumulatively by this update, only filled in `latest_only` mode
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errata: Vec<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Default)]
+#[derive(Debug, Clone, Serialize, JsonSchema, Default)]
 pub struct UpdatesPkgDetail {
     #[serde(skip_serializing_if = "Option::is_none")]
-    summary: Option<String>,
+    pub summary: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+
+    pub available_updates: Vec<PkgUpdate>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
-    description: Option<String>,
+    pub explanation: Option<Explanation>,
+}
 
-    available_updates: Vec<PkgUpdate>,
+/// Why a package or an update candidate was accepted or rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+    Accepted,
+
+    UnknownName,
+    UnknownEvr,
+    NoUpdates,
+    NoArchCompat,
+    PackageNotFound,
+    LatestVersion,
+
+    NoErrata,
+    ArchMismatch,
+    ModuleDisabled,
+    NoRepository,
+    ErratumNotInRepository,
+    RepositoryNotAvailable,
+    ReleaseverMismatch,
+    ProductMismatch,
+    Superseded,
 }
 
-#[derive(Debug, Clone, Serialize, Default)]
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct Candidate {
+    pub package: Nevra,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub erratum: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repository: Option<String>,
+    pub reason: Reason,
+}
+
+/// Trace of the update resolution for single input package
+#[derive(Debug, Clone, Serialize, JsonSchema, Default)]
+pub struct Explanation {
+    /// Set when the package itself was rejected before looking at update candidates
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<Reason>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub candidates: Vec<Candidate>,
+}
+
+impl Explanation {
+    fn rejected(reason: Reason) -> Self {
+        Explanation {
+            reason: Some(reason),
+            candidates: vec![],
+        }
+    }
+
+    fn candidate(&mut self, package: &Nevra, erratum: Option<&String>, repository: Option<&String>, reason: Reason) {
+        self.candidates.push(Candidate {
+            package: package.clone(),
+            erratum: erratum.cloned(),
+            repository: repository.cloned(),
+            reason,
+        });
+    }
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema, Default)]
 pub struct Response {
-    update_list: Map<String, UpdatesPkgDetail>,
+    pub update_list: Map<String, UpdatesPkgDetail>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    repository_list: Option<Vec<String>>,
+    pub repository_list: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    modules_list: Option<Vec<ModuleSpec>>,
+    pub modules_list: Option<Vec<ModuleSpec>>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    releasever: Option<String>,
+    pub releasever: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    basearch: Option<String>,
+    pub basearch: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub latest_only: bool,
+    /// Requested module streams which are not known
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unknown_modules_list: Vec<ModuleSpec>,
+}
+
+macro_rules! try_reason {
+    ($res:expr, $reason:expr) => {
+        match $res {
+            Some(val) => val,
+            _ => {
+                return Ok(Err($reason));
+            }
+        }
+    };
 }
 
 macro_rules! try_cont {
@@ -75,8 +183,25 @@ macro_rules! try_cont {
     };
 }
 
+/// Installed package resolved in the cache, along with what its update candidates are checked against
+struct Installed<'a> {
+    arch_id: i64,
+    arch_compat: &'a [i64],
+    /// Newer packages of the same name, in ascending order of versions
+    update_pkg_ids: &'a [i64],
+    product_ids: Set<Option<i64>>,
+    valid_releasevers: Set<Option<String>>,
+}
+
+impl Installed<'_> {
+    fn arch_compatible(&self, cache: &Cache, update_pkg_id: i64) -> bool {
+        let arch_id = cache.pkg_details[&update_pkg_id].arch_id;
+        arch_id == self.arch_id || self.arch_compat.contains(&arch_id)
+    }
+}
+
 impl Updates {
-    fn build_nevra(cache: &Cache, update_pkg_id: i64) -> Nevra {
+    pub(crate) fn build_nevra(cache: &Cache, update_pkg_id: i64) -> Nevra {
         let det = &cache.pkg_details[&update_pkg_id];
         let name = &cache.id_to_name[&det.name_id];
         let evr = &cache.id_to_evr[&det.evr_id];
@@ -100,6 +225,57 @@ impl Updates {
         return valid_releasevers;
     }
 
+    /// Finds the installed package and its newer versions. Fails on an unknown architecture,
+    /// and gives the reason when the package has nothing to be updated to.
+    fn resolve_package<'a>(cache: &'a Cache, nevra: &Nevra) -> Result<Result<Installed<'a>, Reason>, String> {
+        let name_id = try_reason!(cache.name_to_id.get(&nevra.name), Reason::UnknownName);
+        let updates = try_reason!(cache.updates.get(name_id), Reason::NoUpdates);
+        let updates_index = try_reason!(cache.updates_index.get(name_id), Reason::NoUpdates);
+
+        let evr_id = try_reason!(cache.evr_to_id.get(&nevra.evr()), Reason::UnknownEvr);
+
+        let arch_id = cache.arch_to_id.get(&nevra.arch).ok_or(format!("arch_id not found : {:?}", nevra.arch))?;
+        let arch_compat = try_reason!(cache.arch_compat.get(arch_id), Reason::NoArchCompat);
+
+        let current_evr_idxs: &[_] = try_reason!(updates_index.get(evr_id), Reason::PackageNotFound);
+        let last_evr_idx = try_reason!(current_evr_idxs.last(), Reason::NoUpdates);
+
+        let pkg_id = current_evr_idxs.iter()
+            .map(|idx| updates[*idx])
+            .find(|pkg_id| cache.pkg_details[pkg_id].arch_id == *arch_id);
+        let pkg_id = try_reason!(pkg_id, Reason::PackageNotFound);
+
+        if updates.last() == Some(&pkg_id) {
+            return Ok(Err(Reason::LatestVersion));
+        }
+
+        let mut original_package_repo_ids = Set::default();
+        if let Some(repoids) = cache.pkgid_to_repoids.get(&pkg_id) {
+            original_package_repo_ids.extend(repoids.iter());
+        }
+
+        Ok(Ok(Installed {
+            arch_id: *arch_id,
+            arch_compat,
+            update_pkg_ids: &updates[last_evr_idx + 1..],
+            product_ids: Self::related_products(cache, &original_package_repo_ids),
+            valid_releasevers: Self::valid_releasevers(cache, &original_package_repo_ids),
+        }))
+    }
+
+    /// Checks whether the update is applicable with the enabled module streams.
+    /// Non-modular packages are always applicable, modular ones only when one
+    /// of their streams is enabled, so non-modular requests get no modular updates.
+    fn module_enabled(cache: &Cache, module_ids: &Set<i64>, update_pkg_id: i64, errata_id: i64) -> bool {
+        let pkg_module_ids = cache.pkgerrata_to_module.get(&(update_pkg_id, errata_id))
+            .or_else(|| cache.pkgid_to_module_ids.get(&update_pkg_id));
+
+        match pkg_module_ids {
+            Some(pkg_module_ids) => pkg_module_ids.iter().any(|m| module_ids.contains(m)),
+            None => true,
+        }
+    }
+
     fn get_repositories(
         cache: &Cache,
         product_ids: &Set<Option<i64>>,
@@ -107,28 +283,36 @@ impl Updates {
         errata_ids: &[i64],
         available_repo_ids: &Set<i64>,
         valid_releasevers: &Set<Option<String>>,
+        mut trace: Option<&mut Vec<(i64, Reason)>>,
     ) -> Set<i64> {
-        let mut errata_repo_ids = Set::default();
+        let mut errata_repo_ids: Set<i64> = Set::default();
 
         for errata_id in errata_ids {
             errata_repo_ids.extend(&cache.errataid_to_repoids[errata_id]);
         }
 
-        let repo_ids = cache.pkgid_to_repoids.get(&update_pkg_id)
-            .map(Set::from_iter)
-            .unwrap_or(Set::default());
+        let mut repo_ids = Set::default();
 
-        let mut repo_ids = repo_ids
-            .intersection(&errata_repo_ids).map(|s| **s).collect::<Set<i64>>()
-            .intersection(available_repo_ids).map(|s| *s).collect::<Set<i64>>();
+        for repo_id in cache.pkgid_to_repoids.get(&update_pkg_id).into_iter().flatten() {
+            let detail = &cache.repo_detail[repo_id];
 
+            let reason = if !errata_repo_ids.contains(repo_id) {
+                Reason::ErratumNotInRepository
+            } else if !available_repo_ids.contains(repo_id) {
+                Reason::RepositoryNotAvailable
+            } else if !valid_releasevers.contains(&detail.releasever) {
+                Reason::ReleaseverMismatch
+            } else if !product_ids.contains(&detail.product_id) {
+                Reason::ProductMismatch
+            } else {
+                repo_ids.insert(*repo_id);
+                Reason::Accepted
+            };
 
-        let repo_ids = repo_ids.clone().into_iter().filter(|repo_id| {
-            let detail = &cache.repo_detail[&repo_id];
-            valid_releasevers.contains(&detail.releasever)
-                && product_ids.contains(&detail.product_id)
-        }).collect();
-
+            if let Some(trace) = trace.as_mut() {
+                trace.push((*repo_id, reason));
+            }
+        }
 
         return repo_ids;
     }
@@ -137,125 +321,188 @@ impl Updates {
         cache: &Cache,
         packages_to_process: &Map<&str, Nevra>,
         available_repo_ids: &Set<i64>,
-        module_ids: &Vec<i64>,
+        module_ids: &Set<i64>,
+        latest_only: bool,
+        explain: bool,
         response: &mut Response,
     ) -> Result<()> {
-        for (pkg, nevra) in packages_to_process.iter() {
-            
-            let name_id = try_cont!(cache.name_to_id.get(&nevra.name), "Name not found");
-            let updates = try_cont!(cache.updates.get(&name_id), "Updates not found");
-            let updates_index = try_cont!(cache.updates_index.get(&name_id), "updates index not found");
-
-            let evr_id = try_cont!(cache.evr_to_id.get(&nevra.evr()), "Evr not found {:?}", nevra);
-
-            let arch_id = cache.arch_to_id.get(&nevra.arch).ok_or(format!("arch_id not found : {:?}", nevra.arch))?;
-            let arch_compat = try_cont!(cache.arch_compat.get(arch_id), "Arch compat not found");
-
-
-            // If nothing is found, use empty list
-            let current_evr_idxs: &[_] = try_cont!(updates_index.get(&evr_id));
+        // Packages are independent of each other, so compute them in parallel, and merge
+        // the results into the response afterwards
+        let pkgs = packages_to_process.iter().collect::<Vec<_>>();
+
+        let pkg_updates = pkgs.par_iter().map(|(pkg, nevra)| {
+            Self::process_package(cache, nevra, available_repo_ids, module_ids, latest_only, explain)
+                .map(|updates| (**pkg, updates))
+        }).collect::<Result<Vec<_>, String>>()?;
+
+        for (pkg, (updates, explanation)) in pkg_updates {
+            let resp_pkg_detail = response.update_list.entry(pkg.into()).or_default();
+            resp_pkg_detail.available_updates.extend(updates);
+            resp_pkg_detail.explanation = explanation;
+        }
+        Ok(())
+    }
 
-            if current_evr_idxs.is_empty() {
-                //error!("package {:?} has no updates", name_id);
+    /// Finds the applicable updates of single package, along with the trace of
+    /// the resolution if `explain` is set
+    fn process_package(
+        cache: &Cache,
+        nevra: &Nevra,
+        available_repo_ids: &Set<i64>,
+        module_ids: &Set<i64>,
+        latest_only: bool,
+        explain: bool,
+    ) -> Result<(Vec<PkgUpdate>, Option<Explanation>), String> {
+        let mut trace = if explain { Some(Explanation::default()) } else { None };
+
+        let installed = match Self::resolve_package(cache, nevra)? {
+            Ok(installed) => installed,
+            Err(reason) => return Ok((vec![], trace.map(|_| Explanation::rejected(reason)))),
+        };
+
+        // Applicable updates, in ascending order of package versions
+        let mut pkg_updates: Vec<(i64, PkgUpdate)> = vec![];
+
+        for update_pkg_id in installed.update_pkg_ids {
+            let nevra = Self::build_nevra(cache, *update_pkg_id);
+
+            let errata_ids = match cache.pkgid_to_errataids.get(update_pkg_id) {
+                Some(errata_ids) => errata_ids,
+                None => {
+                    if let Some(trace) = trace.as_mut() {
+                        trace.candidate(&nevra, None, None, Reason::NoErrata);
+                    }
+                    continue;
+                }
+            };
+            if !installed.arch_compatible(cache, *update_pkg_id) {
+                if let Some(trace) = trace.as_mut() {
+                    trace.candidate(&nevra, None, None, Reason::ArchMismatch);
+                }
                 continue;
             }
+            for errata_id in errata_ids {
+                let erratum = &cache.errataid_to_name[errata_id];
 
-            let mut current_nevra_pkg_id = None;
-
-            for current_evr_idx in current_evr_idxs {
-                //error!("current evr idx : => {:?}", current_evr_idx);
-
-                let pkg_id = cache.updates[&name_id][*current_evr_idx as usize];
-                let current_nevra_arch_id = &cache.pkg_details[&pkg_id].arch_id;
+                if !Self::module_enabled(cache, module_ids, *update_pkg_id, *errata_id) {
+                    if let Some(trace) = trace.as_mut() {
+                        trace.candidate(&nevra, Some(erratum), None, Reason::ModuleDisabled);
+                    }
+                    continue;
+                }
 
-                //trace!("Package archs : {:?}, {:?}", current_nevra_arch_id, arch_id);
-                if current_nevra_arch_id == arch_id {
-                    current_nevra_pkg_id = Some(pkg_id);
-                    break;
+                let mut repo_trace = vec![];
+                let mut repo_ids = Self::get_repositories(
+                    cache,
+                    &installed.product_ids,
+                    *update_pkg_id,
+                    &[*errata_id],
+                    available_repo_ids,
+                    &installed.valid_releasevers,
+                    trace.as_ref().map(|_| &mut repo_trace),
+                );
+
+                if let Some(trace) = trace.as_mut() {
+                    if repo_trace.is_empty() {
+                        trace.candidate(&nevra, Some(erratum), None, Reason::NoRepository);
+                    }
+                    for (repo_id, reason) in repo_trace {
+                        let label = &cache.repo_detail[&repo_id].label;
+                        trace.candidate(&nevra, Some(erratum), Some(label), reason);
+                    }
                 }
-            }
 
-            if current_nevra_pkg_id.is_none() {
-                //error!("Package with NEVRA: {:?} not found", nevra);
-                continue;
-            } else {
-                //error!("Package with NEVRA: {:?} Found", nevra);
+                for repo_id in repo_ids {
+                    let repo_det = &cache.repo_detail[&repo_id];
+                    pkg_updates.push((*update_pkg_id, PkgUpdate {
+                        package: nevra.clone(),
+                        erratum: erratum.clone(),
+                        repository: Some(repo_det.label.clone()),
+                        basearch: repo_det.basearch.clone(),
+                        releasever: repo_det.releasever.clone().unwrap_or_default(),
+                        errata: vec![],
+                    }));
+                }
             }
+        }
 
-            let current_nevra_pkg_id = current_nevra_pkg_id.unwrap();
-
-            //println!("Package FOUND {:?} = {:?} with NEVRA:{:?} Found", current_nevra_pkg_id, arch_id, nevra);
-
-            let resp_pkg_detail = response.update_list.entry((*pkg).into()).or_default();
+        if latest_only {
+            pkg_updates = Self::latest_updates(pkg_updates);
 
-            let last_version_pkg_id = updates.last();
-            if last_version_pkg_id == Some(&current_nevra_pkg_id) {
-                //println!("Package is last, no updates");
-                continue;
-            }
-
-            if cache.pkg_details[&current_nevra_pkg_id].arch_id != *arch_id {
-                //panic!("Arch id of found package wrong")
+            // Accepted candidates older than the latest update are superseded by it
+            if let (Some(trace), Some((_, latest))) = (trace.as_mut(), pkg_updates.last()) {
+                for candidate in trace.candidates.iter_mut() {
+                    if candidate.reason == Reason::Accepted && candidate.package != latest.package {
+                        candidate.reason = Reason::Superseded;
+                    }
+                }
             }
+        }
+        Ok((pkg_updates.into_iter().map(|(_, u)| u).collect(), trace))
+    }
 
-            let mut original_package_repo_ids = Set::default();
+    /// Unpatched errata of single package, resolved the same way as its updates, but looking
+    /// only at the repositories which would be accepted if they were available
+    fn unpatched_package_errata(
+        cache: &Cache,
+        nevra: &Nevra,
+        available_repo_ids: &Set<i64>,
+        module_ids: &Set<i64>,
+    ) -> Option<Set<i64>> {
+        let installed = Self::resolve_package(cache, nevra).ok()?.ok()?;
 
-            if let Some(repoids) = cache.pkgid_to_repoids.get(&current_nevra_pkg_id) {
-                original_package_repo_ids.extend(repoids.iter());
+        let mut unpatched = Set::default();
+        for update_pkg_id in installed.update_pkg_ids {
+            if !installed.arch_compatible(cache, *update_pkg_id) {
+                continue;
             }
-
-            let product_ids = Self::related_products(cache, &original_package_repo_ids);
-            let valid_releasevers = Self::valid_releasevers(cache, &original_package_repo_ids);
-
-            //println!("Valid prods : {:?}, valid vers : {:?}", product_ids, valid_releasevers);
-            let update_pkg_ids = &updates[current_evr_idxs.last().unwrap() + 1..];
-
-            for update_pkg_id in update_pkg_ids {
-               // println!("Update pkg id : {:?}", update_pkg_id);
-                let errata_ids = try_cont!(cache.pkgid_to_errataids.get(update_pkg_id));
-                let updated_nevra_arch_id = cache.pkg_details[update_pkg_id].arch_id;
-                //println!("Update pkg arch : {:?}, orig arch id : {:?}", updated_nevra_arch_id, arch_id);
-
-                if updated_nevra_arch_id != *arch_id && !arch_compat.contains(&updated_nevra_arch_id)
-                {
-                    //println!("Filteroing out id : {:?}, wrong arch", update_pkg_id);
+            for errata_id in cache.pkgid_to_errataids.get(update_pkg_id).into_iter().flatten() {
+                if !Self::module_enabled(cache, module_ids, *update_pkg_id, *errata_id) {
                     continue;
                 }
-                let nevra = Self::build_nevra(cache, *update_pkg_id);
-                for errata_id in errata_ids {
-                    if module_ids.len() > 0 {
-                        if let Some(pkg_errata_mods) = cache.pkgerrata_to_module.get(&(*update_pkg_id, *errata_id)) {
-                            if Set::from_iter(module_ids).intersection(&Set::from_iter(pkg_errata_mods)).count() == 0 {
-                                continue;
-                            }
-                        }
-                    }
-
-                    let mut repo_ids = Self::get_repositories(
-                        cache,
-                        &product_ids,
-                        *update_pkg_id,
-                        &[*errata_id],
-                        &available_repo_ids,
-                        &valid_releasevers,
-                    );
-
-                   // println!("Repoids avail : {:?}", repo_ids);
-
-                    for repo_id in repo_ids {
-                        let repo_det = &cache.repo_detail[&repo_id];
-                        resp_pkg_detail.available_updates.push(PkgUpdate {
-                            package: nevra.clone(),
-                            erratum: cache.errataid_to_name[errata_id].clone(),
-                            repository: Some(repo_det.label.clone()),
-                            basearch: repo_det.basearch.clone(),
-                            releasever: repo_det.releasever.clone().unwrap_or_default(),
-                        });
-                    }
+                let errata_repo_ids = &cache.errataid_to_repoids[errata_id];
+                let mut repo_ids = cache.pkgid_to_repoids.get(update_pkg_id).into_iter().flatten()
+                    .filter(|repo_id| errata_repo_ids.contains(repo_id))
+                    .filter(|repo_id| {
+                        let detail = &cache.repo_detail[repo_id];
+                        installed.valid_releasevers.contains(&detail.releasever)
+                            && installed.product_ids.contains(&detail.product_id)
+                    })
+                    .peekable();
+
+                if repo_ids.peek().is_some() && !repo_ids.any(|repo_id| available_repo_ids.contains(repo_id)) {
+                    unpatched.insert(*errata_id);
                 }
             }
         }
-        Ok(())
+        Some(unpatched)
+    }
+
+    /// Keeps only updates to the newest applicable package, each carrying
+    /// the errata fixed by all applicable updates up to that package.
+    fn latest_updates(pkg_updates: Vec<(i64, PkgUpdate)>) -> Vec<(i64, PkgUpdate)> {
+        let latest_pkg_id = match pkg_updates.last() {
+            Some((id, _)) => *id,
+            None => return pkg_updates,
+        };
+
+        let errata = pkg_updates.iter()
+            .map(|(_, u)| u.erratum.clone())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        // Newest package is offered once per repository
+        let mut seen_repos = Set::default();
+        let mut res = vec![];
+        for (pkg_id, mut update) in pkg_updates {
+            if pkg_id != latest_pkg_id || !seen_repos.insert(update.repository.clone()) {
+                continue;
+            }
+            update.errata = errata.clone();
+            res.push((pkg_id, update));
+        }
+        res
     }
 
     fn process_repositories(
@@ -308,11 +555,15 @@ impl Updates {
         for pkg in &data.package_list {
             //println!("pkg: {:?}", pkg);
             if let Ok(nevra) = Nevra::from_str(pkg.as_str()) {
-                response.update_list.entry(pkg.clone()).or_default();
+                let detail = response.update_list.entry(pkg.clone()).or_default();
                 if let Some(id) = cache.name_to_id.get(&nevra.name) {
                     if let Some(up) = cache.updates_index.get(id) {
                         filtered_pkgs_to_process.insert(pkg.as_str(), nevra);
+                    } else if data.explain {
+                        detail.explanation = Some(Explanation::rejected(Reason::NoUpdates));
                     }
+                } else if data.explain {
+                    detail.explanation = Some(Explanation::rejected(Reason::UnknownName));
                 }
             } else {
                 //println!("Not a valid nevra {:?}", pkg)
@@ -322,20 +573,27 @@ impl Updates {
         filtered_pkgs_to_process
     }
 
-    pub fn calc_updates(cache: &Cache, data: Request) -> Result<Response> {
-        let mut response = Response::default();
-
-        let mut module_ids = vec![];
+    fn process_modules(cache: &Cache, data: &Request, response: &mut Response) -> Set<i64> {
+        let mut module_ids = Set::default();
         if let Some(ref modules_list) = data.modules_list {
             response.modules_list = Some(modules_list.clone());
             for m in modules_list.iter() {
-                if let Some(module) = cache.modulename_to_id.get(&(m.module_name.clone(), m.module_stream.clone())) {
-                    module_ids.push(*module);
+                if let Some(ids) = cache.modulename_to_id.get(&(m.module_name.clone(), m.module_stream.clone())) {
+                    module_ids.extend(ids.iter().copied());
+                } else {
+                    response.unknown_modules_list.push(m.clone());
                 }
             }
         }
+        module_ids
+    }
 
+    pub fn calc_updates(cache: &Cache, data: Request) -> Result<Response> {
+        let mut response = Response::default();
+
+        let module_ids = Self::process_modules(cache, &data, &mut response);
         let available_repo_ids = Self::process_repositories(cache, &data, &mut response);
+        response.latest_only = data.latest_only;
 
         let mut packages_to_process = Self::process_input_packages(cache, &data, &mut response);
 
@@ -353,8 +611,257 @@ impl Updates {
             &packages_to_process,
             &available_repo_ids,
             &module_ids,
+            data.latest_only,
+            data.explain,
             &mut response,
         )?;
         Ok(response)
     }
-}
\ No newline at end of file
+
+    /// Errata of newer packages, which are shipped in repositories of the products and
+    /// releasevers of the installed packages, none of which is enabled on the system
+    pub fn unpatched_errata(cache: &Cache, data: &Request) -> Set<i64> {
+        let mut response = Response::default();
+        let module_ids = Self::process_modules(cache, data, &mut response);
+        let available_repo_ids = Self::process_repositories(cache, data, &mut response);
+        let packages = Self::process_input_packages(cache, data, &mut response);
+
+        packages.values()
+            .filter_map(|nevra| Self::unpatched_package_errata(cache, nevra, &available_repo_ids, &module_ids))
+            .flatten()
+            .collect()
+    }
+
+    /// Calculates updates for multiple systems. Systems which share repositories, modules,
+    /// releasever and basearch are evaluated together, so every package shared between them
+    /// is resolved only once.
+    pub fn calc_updates_batch(cache: &Cache, data: BatchRequest) -> Result<BatchResponse> {
+        let mut groups: BTreeMap<_, Vec<(String, Request)>> = BTreeMap::new();
+        for (system, req) in data.systems {
+            let key = (
+                req.repository_list.clone(),
+                req.modules_list.clone(),
+                req.releasever.clone(),
+                req.basearch.clone(),
+                req.latest_only,
+                req.explain,
+            );
+            groups.entry(key).or_default().push((system, req));
+        }
+
+        let mut response = BatchResponse::default();
+        for (_, systems) in groups {
+            let mut shared = systems[0].1.clone();
+            shared.package_list = systems.iter()
+                .flat_map(|(_, req)| req.package_list.iter().cloned())
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect();
+
+            match Self::calc_updates(cache, shared) {
+                Ok(shared) => {
+                    for (system, req) in systems {
+                        response.systems.insert(system, shared.for_packages(&req.package_list));
+                    }
+                }
+                // A package of one system fails the whole group, so evaluate the systems
+                // separately, failing only those which sent it
+                Err(_) => {
+                    for (system, req) in systems {
+                        match Self::calc_updates(cache, req) {
+                            Ok(res) => {
+                                response.systems.insert(system, res);
+                            }
+                            Err(e) => {
+                                response.errors.insert(system, e.to_string());
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(response)
+    }
+}
+
+impl Response {
+    /// Creates a response containing only the listed packages
+    fn for_packages(&self, package_list: &[String]) -> Response {
+        let update_list = package_list.iter()
+            .filter_map(|pkg| self.update_list.get(pkg).map(|detail| (pkg.clone(), detail.clone())))
+            .collect();
+
+        Response {
+            update_list,
+            repository_list: self.repository_list.clone(),
+            modules_list: self.modules_list.clone(),
+            releasever: self.releasever.clone(),
+            basearch: self.basearch.clone(),
+            latest_only: self.latest_only,
+            unknown_modules_list: self.unknown_modules_list.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::CACHE;
+
+    fn request(package: &str, repos: &[&str], latest_only: bool) -> Request {
+        Request {
+            package_list: vec![package.to_string()],
+            repository_list: Some(repos.iter().map(|r| r.to_string()).collect()),
+            latest_only,
+            ..Default::default()
+        }
+    }
+
+    fn updates(res: &Response, package: &str) -> Vec<(String, String, Vec<String>)> {
+        res.update_list[package].available_updates.iter()
+            .map(|u| (u.package.to_string(), u.erratum.clone(), u.errata.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn all_updates() {
+        let res = Updates::calc_updates(&CACHE, request("kernel-1.0-1.el7.x86_64", &["rhel-7-server-rpms"], false)).unwrap();
+        assert_eq!(updates(&res, "kernel-1.0-1.el7.x86_64"), vec![
+            ("kernel-1.1-1.el7.x86_64".to_string(), "RHSA-2020:0001".to_string(), vec![]),
+            ("kernel-1.2-1.el7.x86_64".to_string(), "RHBA-2020:0002".to_string(), vec![]),
+        ]);
+    }
+
+    #[test]
+    fn latest_only_keeps_newest_package_with_all_errata() {
+        let res = Updates::calc_updates(&CACHE, request("kernel-1.0-1.el7.x86_64", &["rhel-7-server-rpms"], true)).unwrap();
+        assert!(res.latest_only);
+        assert_eq!(updates(&res, "kernel-1.0-1.el7.x86_64"), vec![(
+            "kernel-1.2-1.el7.x86_64".to_string(),
+            "RHBA-2020:0002".to_string(),
+            vec!["RHBA-2020:0002".to_string(), "RHSA-2020:0001".to_string()],
+        )]);
+    }
+
+    #[test]
+    fn latest_only_offers_newest_package_once_per_repository() {
+        let repos = ["rhel-7-server-rpms", "rhel-7-server-extras-rpms"];
+        let res = Updates::calc_updates(&CACHE, request("kernel-1.0-1.el7.x86_64", &repos, true)).unwrap();
+        let detail = &res.update_list["kernel-1.0-1.el7.x86_64"];
+        let mut repos = detail.available_updates.iter().map(|u| u.repository.clone().unwrap()).collect::<Vec<_>>();
+        repos.sort();
+        assert_eq!(repos, vec!["rhel-7-server-extras-rpms", "rhel-7-server-rpms"]);
+        assert!(detail.available_updates.iter().all(|u| u.package.to_string() == "kernel-1.2-1.el7.x86_64"));
+    }
+
+    #[test]
+    fn latest_only_without_updates() {
+        let res = Updates::calc_updates(&CACHE, request("kernel-1.2-1.el7.x86_64", &["rhel-7-server-rpms"], true)).unwrap();
+        assert!(res.update_list["kernel-1.2-1.el7.x86_64"].available_updates.is_empty());
+    }
+
+    #[test]
+    fn modular_updates_of_every_stream_id() {
+        let package = "postgresql-9.6.1-1.module.x86_64";
+        let mut req = request(package, &["rhel-7-server-rpms"], false);
+        let res = Updates::calc_updates(&CACHE, req.clone()).unwrap();
+        assert!(res.update_list[package].available_updates.is_empty());
+
+        req.modules_list = Some(vec![ModuleSpec { module_name: "postgresql".into(), module_stream: "9.6".into() }]);
+        let res = Updates::calc_updates(&CACHE, req).unwrap();
+        assert_eq!(updates(&res, package), vec![
+            ("postgresql-9.6.2-1.module.x86_64".to_string(), "RHEA-2020:0003".to_string(), vec![]),
+        ]);
+        assert!(res.unknown_modules_list.is_empty());
+    }
+
+    fn trace(res: &Response, package: &str) -> Vec<(String, Option<String>, Option<String>, Reason)> {
+        res.update_list[package].explanation.as_ref().unwrap().candidates.iter()
+            .map(|c| (c.package.to_string(), c.erratum.clone(), c.repository.clone(), c.reason))
+            .collect()
+    }
+
+    fn candidate(package: &str, erratum: &str, repository: &str, reason: Reason) -> (String, Option<String>, Option<String>, Reason) {
+        (package.to_string(), Some(erratum.to_string()), Some(repository.to_string()), reason)
+    }
+
+    #[test]
+    fn explain_candidates() {
+        let mut req = request("kernel-1.0-1.el7.x86_64", &["rhel-7-server-extras-rpms", "other-7-rpms"], false);
+        req.explain = true;
+        let res = Updates::calc_updates(&CACHE, req).unwrap();
+        let mut candidates = trace(&res, "kernel-1.0-1.el7.x86_64");
+        candidates.sort_by(|a, b| (&a.0, &a.2).cmp(&(&b.0, &b.2)));
+        assert_eq!(candidates, vec![
+            candidate("kernel-1.1-1.el7.x86_64", "RHSA-2020:0001", "rhel-7-server-rpms", Reason::RepositoryNotAvailable),
+            candidate("kernel-1.2-1.el7.x86_64", "RHBA-2020:0002", "rhel-7-server-extras-rpms", Reason::Accepted),
+            candidate("kernel-1.2-1.el7.x86_64", "RHBA-2020:0002", "rhel-7-server-rpms", Reason::RepositoryNotAvailable),
+            candidate("kernel-1.3-1.el7.x86_64", "OTHER-2020:0004", "other-7-rpms", Reason::ProductMismatch),
+        ]);
+    }
+
+    #[test]
+    fn explain_superseded_by_latest() {
+        let mut req = request("kernel-1.0-1.el7.x86_64", &["rhel-7-server-rpms"], true);
+        req.explain = true;
+        let res = Updates::calc_updates(&CACHE, req).unwrap();
+        let candidates = trace(&res, "kernel-1.0-1.el7.x86_64");
+        assert!(candidates.contains(&candidate("kernel-1.1-1.el7.x86_64", "RHSA-2020:0001", "rhel-7-server-rpms", Reason::Superseded)));
+        assert!(candidates.contains(&candidate("kernel-1.2-1.el7.x86_64", "RHBA-2020:0002", "rhel-7-server-rpms", Reason::Accepted)));
+    }
+
+    #[test]
+    fn explain_rejected_packages() {
+        let mut req = request("kernel-1.3-1.el7.x86_64", &["other-7-rpms"], false);
+        req.package_list.push("unknown-1.0-1.el7.x86_64".to_string());
+        req.package_list.push("kernel-0.1-1.el7.x86_64".to_string());
+        req.explain = true;
+        let res = Updates::calc_updates(&CACHE, req).unwrap();
+        let reason = |package: &str| res.update_list[package].explanation.as_ref().unwrap().reason;
+        assert_eq!(reason("kernel-1.3-1.el7.x86_64"), Some(Reason::LatestVersion));
+        assert_eq!(reason("unknown-1.0-1.el7.x86_64"), Some(Reason::UnknownName));
+        assert_eq!(reason("kernel-0.1-1.el7.x86_64"), Some(Reason::UnknownEvr));
+    }
+
+    #[test]
+    fn no_explanation_unless_requested() {
+        let res = Updates::calc_updates(&CACHE, request("kernel-1.0-1.el7.x86_64", &["rhel-7-server-rpms"], false)).unwrap();
+        assert!(res.update_list["kernel-1.0-1.el7.x86_64"].explanation.is_none());
+    }
+
+    fn batch(systems: &[(&str, Request)]) -> BatchResponse {
+        let systems = systems.iter().map(|(name, req)| (name.to_string(), req.clone())).collect();
+        Updates::calc_updates_batch(&CACHE, BatchRequest { systems }).unwrap()
+    }
+
+    #[test]
+    fn batch_matches_per_system_updates() {
+        let repos = ["rhel-7-server-rpms", "rhel-7-server-extras-rpms"];
+        let a = request("kernel-1.0-1.el7.x86_64", &repos, false);
+        let mut b = request("kernel-1.1-1.el7.x86_64", &repos, false);
+        b.package_list.push("postgresql-9.6.1-1.module.x86_64".to_string());
+        let res = batch(&[("a", a.clone()), ("b", b.clone())]);
+
+        assert!(res.errors.is_empty());
+        for (system, req) in &[("a", a), ("b", b)] {
+            let single = Updates::calc_updates(&CACHE, req.clone()).unwrap();
+            assert_eq!(json::to_value(&res.systems[*system]).unwrap(), json::to_value(&single).unwrap());
+        }
+    }
+
+    #[test]
+    fn batch_falls_back_per_system() {
+        let repos = ["rhel-7-server-rpms"];
+        let a = request("kernel-1.0-1.el7.x86_64", &repos, false);
+        let b = request("kernel-1.1-1.el7.x86_64", &repos, false);
+        let invalid = request("kernel-1.0-1.el7.bogus", &repos, false);
+        let res = batch(&[("a", a.clone()), ("b", b.clone()), ("invalid", invalid)]);
+
+        assert_eq!(res.systems.keys().collect::<Vec<_>>(), vec!["a", "b"]);
+        assert!(res.errors["invalid"].contains("arch_id not found"));
+        for (system, req) in &[("a", a), ("b", b)] {
+            let single = Updates::calc_updates(&CACHE, req.clone()).unwrap();
+            assert_eq!(json::to_value(&res.systems[*system]).unwrap(), json::to_value(&single).unwrap());
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use crate::prelude::*;
+use crate::Cache;
+use crate::calc::updates::{self, Updates};
+use crate::cvss::{CvssV2, CvssV3};
+
+use std::collections::BTreeSet;
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Default)]
+pub struct Request {
+    #[serde(flatten)]
+    pub system: updates::Request,
+
+    /// Return CVSS vectors of the CVEs parsed into their metrics
+    #[serde(default)]
+    pub cvss_vectors: bool,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema, Default)]
+pub struct CveVectors {
+    pub cvss3: Option<CvssV3>,
+    pub cvss2: Option<CvssV2>,
+}
+
+/// Aggregated risk of all CVEs the system is exposed to
+#[derive(Debug, Serialize, JsonSchema, Default)]
+pub struct RiskSummary {
+    /// Highest CVSS base score, v3 when the CVE has one, v2 otherwise
+    pub highest_cvss: Option<f64>,
+    /// CVE with the highest score
+    pub highest_cvss_cve: Option<String>,
+    /// Number of CVEs per impact level
+    pub impact_counts: BTreeMap<String, usize>,
+    /// Number of CVEs fixed by available updates
+    pub fixable_count: usize,
+    /// Number of CVEs without a fix available to the system
+    pub unpatched_count: usize,
+}
+
+#[derive(Debug, Serialize, JsonSchema, Default)]
+pub struct Response {
+    /// CVEs fixed by the errata of available updates
+    pub cve_list: Vec<String>,
+    /// CVEs fixed by errata of newer packages, shipped only in repositories of the products
+    /// and releasevers of the system, which it does not have enabled
+    pub unpatched_cve_list: Vec<String>,
+    pub risk: RiskSummary,
+    /// Parsed CVSS vectors of the CVEs in both lists, when requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cvss_vectors: Option<BTreeMap<String, CveVectors>>,
+}
+
+fn errata_cves<'a>(cache: &'a Cache, erratum: &str) -> impl Iterator<Item=&'a i64> {
+    cache.errataname_to_id.get(erratum)
+        .and_then(|id| cache.errataid_to_cveids.get(id))
+        .into_iter()
+        .flatten()
+}
+
+fn summarize(cache: &Cache, fixable: &BTreeSet<i64>, unpatched: &BTreeSet<i64>) -> RiskSummary {
+    let mut risk = RiskSummary {
+        fixable_count: fixable.len(),
+        unpatched_count: unpatched.len(),
+        ..Default::default()
+    };
+    for cve in fixable.iter().chain(unpatched.iter()).filter_map(|id| cache.cve_detail.get(id)) {
+        *risk.impact_counts.entry(cve.impact.clone()).or_default() += 1;
+
+        if let Some(score) = cve.cvss3_score.or(cve.cvss2_score) {
+            if risk.highest_cvss < Some(score) {
+                risk.highest_cvss = Some(score);
+                risk.highest_cvss_cve = Some(cve.name.clone());
+            }
+        }
+    }
+    risk
+}
+
+/// CVEs the system is exposed to, i.e. those fixed by applicable updates, and those fixed
+/// in packages the system has no access to
+pub fn get_vulnerabilities(cache: &Cache, req: Request) -> Result<Response> {
+    let mut system = req.system;
+    // Every applicable update is needed, not only the latest ones
+    system.latest_only = false;
+    system.explain = false;
+    let unpatched_errata = Updates::unpatched_errata(cache, &system);
+    let updates = Updates::calc_updates(cache, system)?;
+
+    let mut fixable = BTreeSet::new();
+    for detail in updates.update_list.values() {
+        for update in detail.available_updates.iter() {
+            fixable.extend(errata_cves(cache, &update.erratum));
+        }
+    }
+    let unpatched: BTreeSet<i64> = unpatched_errata.iter()
+        .filter_map(|id| cache.errataid_to_cveids.get(id))
+        .flatten()
+        .filter(|id| !fixable.contains(*id))
+        .cloned()
+        .collect();
+
+    let names = |ids: &BTreeSet<i64>| -> Vec<String> {
+        let names: BTreeSet<_> = ids.iter().filter_map(|id| cache.cve_detail.get(id)).map(|cve| cve.name.clone()).collect();
+        names.into_iter().collect()
+    };
+    let cvss_vectors = match req.cvss_vectors {
+        true => Some(fixable.iter().chain(unpatched.iter())
+            .filter_map(|id| cache.cve_detail.get(id))
+            .map(|cve| (cve.name.clone(), CveVectors {
+                cvss3: cve.cvss3_vector.clone().and_then(Result::ok),
+                cvss2: cve.cvss2_vector.clone().and_then(Result::ok),
+            }))
+            .collect()),
+        false => None,
+    };
+
+    Ok(Response {
+        cve_list: names(&fixable),
+        unpatched_cve_list: names(&unpatched),
+        risk: summarize(cache, &fixable, &unpatched),
+        cvss_vectors,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::CACHE;
+
+    fn request(repos: &[&str]) -> Request {
+        Request {
+            system: updates::Request {
+                package_list: vec!["kernel-1.0-1.el7.x86_64".to_string()],
+                repository_list: Some(repos.iter().map(|r| r.to_string()).collect()),
+                ..Default::default()
+            },
+            cvss_vectors: false,
+        }
+    }
+
+    #[test]
+    fn fixable_and_unpatched() {
+        let res = get_vulnerabilities(&CACHE, request(&["rhel-7-server-extras-rpms"])).unwrap();
+        assert_eq!(res.cve_list, vec!["CVE-2020-0002"]);
+        // The fix of the first CVE is only in the base repository, which is not enabled
+        assert_eq!(res.unpatched_cve_list, vec!["CVE-2020-0001"]);
+        assert_eq!(res.risk.fixable_count, 1);
+        assert_eq!(res.risk.unpatched_count, 1);
+        assert_eq!(res.risk.highest_cvss_cve.as_deref(), Some("CVE-2020-0001"));
+    }
+
+    #[test]
+    fn all_fixable() {
+        let res = get_vulnerabilities(&CACHE, request(&["rhel-7-server-rpms", "other-7-rpms"])).unwrap();
+        assert_eq!(res.cve_list, vec!["CVE-2020-0001", "CVE-2020-0002"]);
+        assert!(res.unpatched_cve_list.is_empty());
+    }
+
+    #[test]
+    fn without_repositories_all_unpatched() {
+        let res = get_vulnerabilities(&CACHE, request(&[])).unwrap();
+        assert!(res.cve_list.is_empty());
+        assert_eq!(res.unpatched_cve_list, vec!["CVE-2020-0001", "CVE-2020-0002"]);
+    }
+
+    #[test]
+    fn other_product_is_not_unpatched() {
+        // OTHER-2020:0004 updates kernel only in the repository of another product
+        let unpatched = Updates::unpatched_errata(&CACHE, &request(&[]).system);
+        let names: BTreeSet<_> = unpatched.iter().map(|id| CACHE.errataid_to_name[id].as_str()).collect();
+        assert_eq!(names, vec!["RHBA-2020:0002", "RHSA-2020:0001"].into_iter().collect());
+    }
+}
@@ -0,0 +1,450 @@
+//! Parsing of CVSS v2 and v3.x vector strings and calculation of their base scores.
+//!
+//! Only base metrics are kept, temporal and environmental metrics are accepted in vectors
+//! but ignored. Scores follow the specifications at <https://www.first.org/cvss/>.
+
+use crate::prelude::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CvssError {
+    UnsupportedVersion(String),
+    Malformed(String),
+    UnknownMetric(String),
+    DuplicateMetric(String),
+    MissingMetric(&'static str),
+    InvalidValue(String, String),
+    ScoreMismatch { stored: f64, calculated: f64 },
+}
+
+impl fmt::Display for CvssError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CvssError::UnsupportedVersion(v) => write!(f, "Unsupported CVSS version {:?}", v),
+            CvssError::Malformed(part) => write!(f, "Malformed CVSS metric {:?}", part),
+            CvssError::UnknownMetric(m) => write!(f, "Unknown CVSS metric {}", m),
+            CvssError::DuplicateMetric(m) => write!(f, "Duplicate CVSS metric {}", m),
+            CvssError::MissingMetric(m) => write!(f, "Missing CVSS metric {}", m),
+            CvssError::InvalidValue(m, v) => write!(f, "Invalid value {:?} of CVSS metric {}", v, m),
+            CvssError::ScoreMismatch { stored, calculated } => {
+                write!(f, "Stored score {} does not match the base score {} of the vector", stored, calculated)
+            }
+        }
+    }
+}
+
+impl Error for CvssError {}
+
+/// Common interface of the vector versions
+pub trait Vector: FromStr<Err=CvssError> {
+    fn version(&self) -> &'static str;
+
+    /// Base metrics with their abbreviations, in the order of the specification
+    fn metrics(&self) -> Vec<(&'static str, &'static str)>;
+
+    fn base_score(&self) -> f64;
+
+    /// Checks the stored score against the one calculated from the vector
+    fn validate(&self, stored: f64) -> Result<(), CvssError> {
+        let calculated = self.base_score();
+        match (stored - calculated).abs() < 0.05 {
+            true => Ok(()),
+            false => Err(CvssError::ScoreMismatch { stored, calculated }),
+        }
+    }
+}
+
+/// Defines a metric enum along with the abbreviations of its values used in vectors
+macro_rules! metric {
+    ($(#[$meta:meta])* $name:ident { $($variant:ident = $abbr:literal),+ $(,)? }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+        #[serde(rename_all = "snake_case")]
+        pub enum $name {
+            $($variant),+
+        }
+
+        impl $name {
+            fn parse(metric: &str, value: &str) -> Result<Self, CvssError> {
+                match value {
+                    $($abbr => Ok($name::$variant),)+
+                    _ => Err(CvssError::InvalidValue(metric.to_string(), value.to_string())),
+                }
+            }
+
+            pub fn abbr(self) -> &'static str {
+                match self {
+                    $($name::$variant => $abbr),+
+                }
+            }
+        }
+    };
+}
+
+metric!(
+    /// Context by which the vulnerability can be exploited, v2 has no physical access
+    AttackVector { Network = "N", AdjacentNetwork = "A", Local = "L", Physical = "P" }
+);
+metric!(AttackComplexity { Low = "L", High = "H" });
+metric!(PrivilegesRequired { None = "N", Low = "L", High = "H" });
+metric!(UserInteraction { None = "N", Required = "R" });
+metric!(Scope { Unchanged = "U", Changed = "C" });
+metric!(Impact { None = "N", Low = "L", High = "H" });
+metric!(AccessComplexity { Low = "L", Medium = "M", High = "H" });
+metric!(Authentication { None = "N", Single = "S", Multiple = "M" });
+metric!(ImpactV2 { None = "N", Partial = "P", Complete = "C" });
+
+/// Splits a vector into its metrics, dropping the given temporal and environmental ones.
+/// Base metrics are taken out of the result, anything left over is unknown.
+fn metrics<'a>(parts: impl Iterator<Item=&'a str>, ignored: &[&str]) -> Result<BTreeMap<&'a str, &'a str>, CvssError> {
+    let mut res = BTreeMap::new();
+    for part in parts {
+        let mut kv = part.splitn(2, ':');
+        let (metric, value) = match (kv.next(), kv.next()) {
+            (Some(m), Some(v)) if !m.is_empty() && !v.is_empty() => (m, v),
+            _ => return Err(CvssError::Malformed(part.to_string())),
+        };
+        if res.insert(metric, value).is_some() {
+            return Err(CvssError::DuplicateMetric(metric.to_string()));
+        }
+    }
+    res.retain(|metric, _| !ignored.contains(metric));
+    Ok(res)
+}
+
+fn take<'a>(metrics: &mut BTreeMap<&'a str, &'a str>, metric: &'static str) -> Result<&'a str, CvssError> {
+    metrics.remove(metric).ok_or(CvssError::MissingMetric(metric))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+pub enum V3Version {
+    #[serde(rename = "3.0")]
+    V3_0,
+    #[serde(rename = "3.1")]
+    V3_1,
+}
+
+/// Base metrics of a CVSS v3.0 or v3.1 vector
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct CvssV3 {
+    pub version: V3Version,
+    pub attack_vector: AttackVector,
+    pub attack_complexity: AttackComplexity,
+    pub privileges_required: PrivilegesRequired,
+    pub user_interaction: UserInteraction,
+    pub scope: Scope,
+    pub confidentiality: Impact,
+    pub integrity: Impact,
+    pub availability: Impact,
+}
+
+const V3_IGNORED: &[&str] = &[
+    "E", "RL", "RC", "CR", "IR", "AR", "MAV", "MAC", "MPR", "MUI", "MS", "MC", "MI", "MA",
+];
+
+impl FromStr for CvssV3 {
+    type Err = CvssError;
+
+    fn from_str(vector: &str) -> Result<Self, Self::Err> {
+        let mut parts = vector.split('/');
+        let version = match parts.next() {
+            Some("CVSS:3.0") => V3Version::V3_0,
+            Some("CVSS:3.1") => V3Version::V3_1,
+            other => return Err(CvssError::UnsupportedVersion(other.unwrap_or_default().to_string())),
+        };
+        let mut m = metrics(parts, V3_IGNORED)?;
+        let res = CvssV3 {
+            version,
+            attack_vector: AttackVector::parse("AV", take(&mut m, "AV")?)?,
+            attack_complexity: AttackComplexity::parse("AC", take(&mut m, "AC")?)?,
+            privileges_required: PrivilegesRequired::parse("PR", take(&mut m, "PR")?)?,
+            user_interaction: UserInteraction::parse("UI", take(&mut m, "UI")?)?,
+            scope: Scope::parse("S", take(&mut m, "S")?)?,
+            confidentiality: Impact::parse("C", take(&mut m, "C")?)?,
+            integrity: Impact::parse("I", take(&mut m, "I")?)?,
+            availability: Impact::parse("A", take(&mut m, "A")?)?,
+        };
+        match m.keys().next() {
+            Some(metric) => Err(CvssError::UnknownMetric(metric.to_string())),
+            None => Ok(res),
+        }
+    }
+}
+
+impl CvssV3 {
+    /// Rounds up to one decimal, v3.1 avoids floating point errors by working with integers
+    fn round_up(&self, value: f64) -> f64 {
+        match self.version {
+            V3Version::V3_0 => (value * 10.0).ceil() / 10.0,
+            V3Version::V3_1 => {
+                let int = (value * 100_000.0).round() as i64;
+                match int % 10_000 {
+                    0 => int as f64 / 100_000.0,
+                    _ => ((int / 10_000) as f64 + 1.0) / 10.0,
+                }
+            }
+        }
+    }
+}
+
+impl Vector for CvssV3 {
+    fn version(&self) -> &'static str {
+        match self.version {
+            V3Version::V3_0 => "3.0",
+            V3Version::V3_1 => "3.1",
+        }
+    }
+
+    fn metrics(&self) -> Vec<(&'static str, &'static str)> {
+        vec![
+            ("AV", self.attack_vector.abbr()),
+            ("AC", self.attack_complexity.abbr()),
+            ("PR", self.privileges_required.abbr()),
+            ("UI", self.user_interaction.abbr()),
+            ("S", self.scope.abbr()),
+            ("C", self.confidentiality.abbr()),
+            ("I", self.integrity.abbr()),
+            ("A", self.availability.abbr()),
+        ]
+    }
+
+    fn base_score(&self) -> f64 {
+        let impact = |i: Impact| -> f64 {
+            match i {
+                Impact::High => 0.56,
+                Impact::Low => 0.22,
+                Impact::None => 0.0,
+            }
+        };
+        let changed = self.scope == Scope::Changed;
+
+        let iss = 1.0 - (1.0 - impact(self.confidentiality)) * (1.0 - impact(self.integrity)) * (1.0 - impact(self.availability));
+        let impact = match changed {
+            true => 7.52 * (iss - 0.029) - 3.25 * (iss - 0.02).powi(15),
+            false => 6.42 * iss,
+        };
+        if impact <= 0.0 {
+            return 0.0;
+        }
+
+        let av: f64 = match self.attack_vector {
+            AttackVector::Network => 0.85,
+            AttackVector::AdjacentNetwork => 0.62,
+            AttackVector::Local => 0.55,
+            AttackVector::Physical => 0.2,
+        };
+        let ac = match self.attack_complexity {
+            AttackComplexity::Low => 0.77,
+            AttackComplexity::High => 0.44,
+        };
+        let pr = match (self.privileges_required, changed) {
+            (PrivilegesRequired::None, _) => 0.85,
+            (PrivilegesRequired::Low, false) => 0.62,
+            (PrivilegesRequired::Low, true) => 0.68,
+            (PrivilegesRequired::High, false) => 0.27,
+            (PrivilegesRequired::High, true) => 0.5,
+        };
+        let ui = match self.user_interaction {
+            UserInteraction::None => 0.85,
+            UserInteraction::Required => 0.62,
+        };
+        let exploitability = 8.22 * av * ac * pr * ui;
+
+        match changed {
+            true => self.round_up((1.08 * (impact + exploitability)).min(10.0)),
+            false => self.round_up((impact + exploitability).min(10.0)),
+        }
+    }
+}
+
+impl fmt::Display for CvssV3 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CVSS:{}", self.version())?;
+        for (metric, value) in self.metrics() {
+            write!(f, "/{}:{}", metric, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Base metrics of a CVSS v2 vector
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct CvssV2 {
+    pub access_vector: AttackVector,
+    pub access_complexity: AccessComplexity,
+    pub authentication: Authentication,
+    pub confidentiality: ImpactV2,
+    pub integrity: ImpactV2,
+    pub availability: ImpactV2,
+}
+
+const V2_IGNORED: &[&str] = &["E", "RL", "RC", "CDP", "TD", "CR", "IR", "AR"];
+
+impl FromStr for CvssV2 {
+    type Err = CvssError;
+
+    /// Parses vectors with or without the surrounding parentheses used by NVD
+    fn from_str(vector: &str) -> Result<Self, Self::Err> {
+        let vector = vector.trim_start_matches('(').trim_end_matches(')');
+        let mut m = metrics(vector.split('/'), V2_IGNORED)?;
+        let access_vector = match take(&mut m, "AV")? {
+            "P" => return Err(CvssError::InvalidValue("AV".to_string(), "P".to_string())),
+            av => AttackVector::parse("AV", av)?,
+        };
+        let res = CvssV2 {
+            access_vector,
+            access_complexity: AccessComplexity::parse("AC", take(&mut m, "AC")?)?,
+            authentication: Authentication::parse("Au", take(&mut m, "Au")?)?,
+            confidentiality: ImpactV2::parse("C", take(&mut m, "C")?)?,
+            integrity: ImpactV2::parse("I", take(&mut m, "I")?)?,
+            availability: ImpactV2::parse("A", take(&mut m, "A")?)?,
+        };
+        match m.keys().next() {
+            Some(metric) => Err(CvssError::UnknownMetric(metric.to_string())),
+            None => Ok(res),
+        }
+    }
+}
+
+impl Vector for CvssV2 {
+    fn version(&self) -> &'static str {
+        "2.0"
+    }
+
+    fn metrics(&self) -> Vec<(&'static str, &'static str)> {
+        vec![
+            ("AV", self.access_vector.abbr()),
+            ("AC", self.access_complexity.abbr()),
+            ("Au", self.authentication.abbr()),
+            ("C", self.confidentiality.abbr()),
+            ("I", self.integrity.abbr()),
+            ("A", self.availability.abbr()),
+        ]
+    }
+    fn base_score(&self) -> f64 {
+        let impact = |i: ImpactV2| -> f64 {
+            match i {
+                ImpactV2::None => 0.0,
+                ImpactV2::Partial => 0.275,
+                ImpactV2::Complete => 0.660,
+            }
+        };
+        let impact = 10.41 * (1.0 - (1.0 - impact(self.confidentiality)) * (1.0 - impact(self.integrity)) * (1.0 - impact(self.availability)));
+
+        let av: f64 = match self.access_vector {
+            AttackVector::Local | AttackVector::Physical => 0.395,
+            AttackVector::AdjacentNetwork => 0.646,
+            AttackVector::Network => 1.0,
+        };
+        let ac = match self.access_complexity {
+            AccessComplexity::High => 0.35,
+            AccessComplexity::Medium => 0.61,
+            AccessComplexity::Low => 0.71,
+        };
+        let au = match self.authentication {
+            Authentication::Multiple => 0.45,
+            Authentication::Single => 0.56,
+            Authentication::None => 0.704,
+        };
+        let exploitability = 20.0 * av * ac * au;
+
+        let f = if impact == 0.0 { 0.0 } else { 1.176 };
+        let score = ((0.6 * impact) + (0.4 * exploitability) - 1.5) * f;
+        (score * 10.0).round() / 10.0
+    }
+}
+
+impl fmt::Display for CvssV2 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let metrics: Vec<_> = self.metrics().into_iter().map(|(m, v)| format!("{}:{}", m, v)).collect();
+        write!(f, "{}", metrics.join("/"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v3(vector: &str) -> f64 {
+        vector.parse::<CvssV3>().unwrap().base_score()
+    }
+
+    fn v2(vector: &str) -> f64 {
+        vector.parse::<CvssV2>().unwrap().base_score()
+    }
+
+    #[test]
+    fn v3_scores() {
+        assert_eq!(v3("CVSS:3.0/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:N/A:N"), 7.5);
+        assert_eq!(v3("CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H"), 9.8);
+        assert_eq!(v3("CVSS:3.1/AV:L/AC:L/PR:L/UI:N/S:U/C:H/I:H/A:H"), 7.8);
+        assert_eq!(v3("CVSS:3.1/AV:L/AC:H/PR:L/UI:R/S:U/C:L/I:L/A:N"), 3.3);
+        assert_eq!(v3("CVSS:3.1/AV:P/AC:H/PR:H/UI:R/S:U/C:N/I:N/A:N"), 0.0);
+    }
+
+    #[test]
+    fn v3_changed_scope() {
+        assert_eq!(v3("CVSS:3.0/AV:N/AC:L/PR:N/UI:R/S:C/C:L/I:L/A:N"), 6.1);
+        assert_eq!(v3("CVSS:3.1/AV:N/AC:L/PR:L/UI:N/S:C/C:H/I:H/A:H"), 9.9);
+        assert_eq!(v3("CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:C/C:H/I:H/A:H"), 10.0);
+    }
+
+    #[test]
+    fn v3_rounding() {
+        let v30 = "CVSS:3.0/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:N/A:N".parse::<CvssV3>().unwrap();
+        let v31 = "CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:N/A:N".parse::<CvssV3>().unwrap();
+        // Floating point error is rounded up by v3.0, but not by v3.1
+        assert_eq!(v30.round_up(4.000_000_000_000_001), 4.1);
+        assert_eq!(v31.round_up(4.000_000_000_000_001), 4.0);
+        assert_eq!(v31.round_up(4.02), 4.1);
+        assert_eq!(v31.round_up(4.0), 4.0);
+    }
+
+    #[test]
+    fn v2_scores() {
+        assert_eq!(v2("AV:N/AC:L/Au:N/C:P/I:N/A:N"), 5.0);
+        assert_eq!(v2("AV:N/AC:L/Au:N/C:C/I:C/A:C"), 10.0);
+        assert_eq!(v2("AV:N/AC:M/Au:N/C:P/I:P/A:P"), 6.8);
+        assert_eq!(v2("AV:L/AC:L/Au:N/C:C/I:C/A:C"), 7.2);
+        assert_eq!(v2("(AV:N/AC:L/Au:N/C:P/I:N/A:N)"), 5.0);
+        assert_eq!(v2("AV:N/AC:L/Au:N/C:N/I:N/A:N"), 0.0);
+    }
+
+    #[test]
+    fn temporal_metrics_are_ignored() {
+        assert_eq!(v3("CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H/E:P/RL:O/RC:C"), 9.8);
+        assert_eq!(v2("AV:N/AC:L/Au:N/C:P/I:N/A:N/E:F/RL:OF/RC:C"), 5.0);
+    }
+
+    #[test]
+    fn display() {
+        let vector = "CVSS:3.1/AV:A/AC:H/PR:L/UI:R/S:C/C:L/I:N/A:H";
+        assert_eq!(vector.parse::<CvssV3>().unwrap().to_string(), vector);
+        assert_eq!("(AV:L/AC:M/Au:S/C:N/I:P/A:C)".parse::<CvssV2>().unwrap().to_string(), "AV:L/AC:M/Au:S/C:N/I:P/A:C");
+    }
+
+    #[test]
+    fn validate() {
+        let vector = "CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H".parse::<CvssV3>().unwrap();
+        assert_eq!(vector.validate(9.8), Ok(()));
+        assert_eq!(vector.validate(7.5), Err(CvssError::ScoreMismatch { stored: 7.5, calculated: 9.8 }));
+    }
+
+    #[test]
+    fn invalid_vectors() {
+        let err = |vector: &str| vector.parse::<CvssV3>().unwrap_err();
+        assert_eq!(err("AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H"), CvssError::UnsupportedVersion("AV:N".to_string()));
+        assert_eq!(err("CVSS:2.0/AV:N"), CvssError::UnsupportedVersion("CVSS:2.0".to_string()));
+        assert_eq!(err("CVSS:3.1/AV:N/AC/PR:N"), CvssError::Malformed("AC".to_string()));
+        assert_eq!(err("CVSS:3.1/AV:N/AC:"), CvssError::Malformed("AC:".to_string()));
+        assert_eq!(err("CVSS:3.1/AV:N/AV:L/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H"), CvssError::DuplicateMetric("AV".to_string()));
+        assert_eq!(err("CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H/XX:Y"), CvssError::UnknownMetric("XX".to_string()));
+        assert_eq!(err("CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H"), CvssError::MissingMetric("A"));
+        assert_eq!(err("CVSS:3.1/AV:X/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H"), CvssError::InvalidValue("AV".to_string(), "X".to_string()));
+
+        let err = |vector: &str| vector.parse::<CvssV2>().unwrap_err();
+        assert_eq!(err("AV:P/AC:L/Au:N/C:P/I:N/A:N"), CvssError::InvalidValue("AV".to_string(), "P".to_string()));
+        assert_eq!(err("AV:N/AC:L/Au:N/C:P/I:N/A:N/Au:S"), CvssError::DuplicateMetric("Au".to_string()));
+        assert_eq!(err("AV:N/AC:L/Au:N/C:P/I:N/A:N/PR:N"), CvssError::UnknownMetric("PR".to_string()));
+        assert_eq!(err("AV:N/AC:L/C:P/I:N/A:N"), CvssError::MissingMetric("Au"));
+    }
+}
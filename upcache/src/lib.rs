@@ -3,6 +3,12 @@
 pub mod prelude;
 pub mod cache;
 pub mod calc;
+pub mod cvss;
+pub mod matching;
+pub mod timestamp;
+
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
 use crate::prelude::*;
 pub use crate::cache::Cache;
@@ -0,0 +1,197 @@
+//! Matching of user supplied patterns against names of repositories, CVEs, errata and packages.
+//!
+//! Patterns are regular expressions which have to match the whole name. Literal names and
+//! literal prefixes are resolved with a binary search in sorted name index, only the rest is
+//! evaluated with the regex engine. Compiled regexes are kept in a shared LRU cache.
+
+use crate::prelude::*;
+
+use lru::LruCache;
+use regex::RegexBuilder;
+use std::sync::{Arc, Mutex};
+
+/// Longest accepted pattern in bytes
+pub const MAX_PATTERN_LEN: usize = 1024;
+/// Memory limit of a single compiled regex
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+const REGEX_NEST_LIMIT: u32 = 32;
+const COMPILED_CACHE_SIZE: usize = 1024;
+
+lazy_static! {
+    static ref COMPILED: Mutex<LruCache<String, Arc<Regex>>> = Mutex::new(LruCache::new(COMPILED_CACHE_SIZE));
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MatchError {
+    TooLong(usize),
+    TooComplex(String),
+    Invalid(String),
+}
+
+impl fmt::Display for MatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MatchError::TooLong(len) => write!(f, "Pattern is {} bytes long, at most {} are allowed", len, MAX_PATTERN_LEN),
+            MatchError::TooComplex(p) => write!(f, "Pattern {:?} is too complex", p),
+            MatchError::Invalid(e) => write!(f, "Invalid pattern: {}", e),
+        }
+    }
+}
+
+impl Error for MatchError {}
+
+fn compile(pattern: &str) -> Result<Arc<Regex>, MatchError> {
+    if let Some(re) = COMPILED.lock().unwrap().get(&pattern.to_string()) {
+        return Ok(re.clone());
+    }
+
+    let re = RegexBuilder::new(&format!("^(?:{})$", pattern))
+        .size_limit(REGEX_SIZE_LIMIT)
+        .dfa_size_limit(REGEX_SIZE_LIMIT)
+        .nest_limit(REGEX_NEST_LIMIT)
+        .build()
+        .map_err(|e| match e {
+            regex::Error::CompiledTooBig(_) => MatchError::TooComplex(pattern.to_string()),
+            e => MatchError::Invalid(e.to_string()),
+        })?;
+
+    let re = Arc::new(re);
+    COMPILED.lock().unwrap().put(pattern.to_string(), re.clone());
+    Ok(re)
+}
+
+const META: &[char] = &['\\', '.', '+', '*', '?', '(', ')', '|', '[', ']', '{', '}', '^', '$'];
+
+/// Literal prefix which every match of the pattern has to start with
+fn literal_prefix(pattern: &str) -> &str {
+    // Alternation could match names outside of the prefix
+    if pattern.contains('|') {
+        return "";
+    }
+    let end = pattern.find(META).unwrap_or(pattern.len());
+    let prefix = &pattern[..end];
+
+    // Last character is optional when followed by a quantifier
+    match pattern[end..].chars().next() {
+        Some('?') | Some('*') | Some('{') => {
+            let last = prefix.char_indices().last().map(|(i, _)| i).unwrap_or(0);
+            &prefix[..last]
+        }
+        _ => prefix,
+    }
+}
+
+/// Sorted list of names, allowing range lookups by prefix
+#[derive(Debug, Default, Clone)]
+pub struct NameIndex {
+    names: Vec<String>,
+}
+
+impl NameIndex {
+    pub fn new(names: impl IntoIterator<Item=String>) -> Self {
+        let mut names = names.into_iter().collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        NameIndex { names }
+    }
+
+    fn with_prefix<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item=&'a String> + 'a {
+        let start = self.names.binary_search_by(|n| n.as_str().cmp(prefix)).unwrap_or_else(|i| i);
+        self.names[start..].iter().take_while(move |n| n.starts_with(prefix))
+    }
+
+    /// Names matching the whole pattern, in sorted order
+    pub fn matching(&self, pattern: &str) -> Result<Vec<String>, MatchError> {
+        if pattern.len() > MAX_PATTERN_LEN {
+            return Err(MatchError::TooLong(pattern.len()));
+        }
+
+        // Plain name
+        if !pattern.contains(META) {
+            return Ok(self.names.binary_search_by(|n| n.as_str().cmp(pattern))
+                .map(|i| vec![self.names[i].clone()])
+                .unwrap_or_default());
+        }
+
+        let prefix = literal_prefix(pattern);
+        // Anything starting with a literal prefix
+        if pattern.len() == prefix.len() + 2 && pattern.ends_with(".*") {
+            return Ok(self.with_prefix(prefix).cloned().collect());
+        }
+
+        let re = compile(pattern)?;
+        Ok(self.with_prefix(prefix).filter(|n| re.is_match(n)).cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefix_of_plain_pattern() {
+        assert_eq!(literal_prefix("rhel-7-server-rpms"), "rhel-7-server-rpms");
+        assert_eq!(literal_prefix("rhel-7-.*"), "rhel-7-");
+        assert_eq!(literal_prefix("CVE-2020-[0-9]+"), "CVE-2020-");
+    }
+
+    #[test]
+    fn prefix_without_optional_character() {
+        assert_eq!(literal_prefix("rhel-7?-rpms"), "rhel-");
+        assert_eq!(literal_prefix("rhel-7*"), "rhel-");
+        assert_eq!(literal_prefix("rhel-7{0,1}"), "rhel-");
+        assert_eq!(literal_prefix("rhel-7+"), "rhel-7");
+        assert_eq!(literal_prefix("a?"), "");
+        assert_eq!(literal_prefix("žluť?"), "žlu");
+    }
+
+    #[test]
+    fn prefix_stops_at_escapes_and_groups() {
+        assert_eq!(literal_prefix(r"rhel\.7"), "rhel");
+        assert_eq!(literal_prefix(r"\d+"), "");
+        assert_eq!(literal_prefix("rhel(-7)?"), "rhel");
+        assert_eq!(literal_prefix("(?i)rhel"), "");
+        assert_eq!(literal_prefix("^rhel"), "");
+    }
+
+    #[test]
+    fn no_prefix_with_alternation() {
+        assert_eq!(literal_prefix("rhel-7|rhel-8"), "");
+        assert_eq!(literal_prefix("rhel-(7|8)"), "");
+    }
+
+    fn index() -> NameIndex {
+        NameIndex::new(vec!["rhel-7", "rhel-7-extras", "rhel-8", "RHEL-9", "fedora", "rhel-7"].into_iter().map(String::from))
+    }
+
+    #[test]
+    fn matching_names() {
+        let index = index();
+        assert_eq!(index.matching("rhel-7").unwrap(), vec!["rhel-7"]);
+        assert!(index.matching("rhel").unwrap().is_empty());
+        assert_eq!(index.matching("rhel-7.*").unwrap(), vec!["rhel-7", "rhel-7-extras"]);
+        assert_eq!(index.matching("rhel-[78]").unwrap(), vec!["rhel-7", "rhel-8"]);
+        assert_eq!(index.matching("rhel-7|fedora").unwrap(), vec!["fedora", "rhel-7"]);
+        assert_eq!(index.matching("(?i)rhel-9").unwrap(), vec!["RHEL-9"]);
+        assert_eq!(index.matching(".*").unwrap().len(), 5);
+    }
+
+    #[test]
+    fn matching_is_anchored() {
+        let index = index();
+        assert_eq!(index.matching("rhel-7-?").unwrap(), vec!["rhel-7"]);
+        assert!(index.matching("hel-7.*").unwrap().is_empty());
+        assert_eq!(index.matching(r"rhel\-7").unwrap(), vec!["rhel-7"]);
+    }
+
+    #[test]
+    fn matching_errors() {
+        let index = index();
+        let long = "a".repeat(MAX_PATTERN_LEN + 1);
+        assert_eq!(index.matching(&long), Err(MatchError::TooLong(MAX_PATTERN_LEN + 1)));
+        let multibyte = "é".repeat(MAX_PATTERN_LEN / 2 + 1);
+        assert_eq!(index.matching(&multibyte), Err(MatchError::TooLong(MAX_PATTERN_LEN + 2)));
+        assert!(matches!(index.matching("rhel-(7"), Err(MatchError::Invalid(_))));
+        assert!(matches!(index.matching("(a{1000}){1000}"), Err(MatchError::TooComplex(_))));
+    }
+}
@@ -1,4 +1,5 @@
 pub use serde::{Serialize, Deserialize, de::DeserializeOwned, Serializer, Deserializer};
+pub use schemars::JsonSchema;
 pub use std::error::Error;
 pub use std::str::FromStr;
 
@@ -13,15 +14,62 @@ pub type Result<T, E = Box<dyn Error>> = std::result::Result<T, E>;
 pub use regex::Regex;
 pub use lazy_static::lazy_static;
 pub use crate::cache::Evr;
+pub use crate::timestamp::Timestamp;
 
 pub use std::{io, fmt};
 pub use std::hash::Hash;
 pub use std::collections::BTreeMap;
 
-#[derive(Debug, Deserialize, Serialize)]
+pub const DEFAULT_PAGE_SIZE: usize = 5000;
+
+/// Requested page of a list response, pages are numbered from 1
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(default)]
 pub struct PagingInfo {
-    page: usize,
-    page_size: usize,
+    pub page: usize,
+    pub page_size: usize,
+}
+
+impl Default for PagingInfo {
+    fn default() -> Self {
+        PagingInfo {
+            page: 1,
+            page_size: DEFAULT_PAGE_SIZE,
+        }
+    }
+}
+
+/// Paging information of a list response
+#[derive(Debug, Clone, Default, Serialize, JsonSchema)]
+pub struct Page {
+    #[serde(flatten)]
+    pub paging: PagingInfo,
+    pub pages: usize,
+}
+
+impl PagingInfo {
+    /// Caps the page size, and fixes up zero page numbers and sizes
+    pub fn limit(&mut self, max_page_size: usize) {
+        self.page = self.page.max(1);
+        self.page_size = self.page_size.max(1).min(max_page_size);
+    }
+
+    /// Sorts the items, so pages are stable between requests, and returns the requested page
+    pub fn paginate<T: Ord>(&self, mut items: Vec<T>) -> (Vec<T>, Page) {
+        items.sort();
+        items.dedup();
+
+        let page_size = self.page_size.max(1);
+        let pages = items.len().div_ceil(page_size);
+        let start = (self.page.max(1) - 1).saturating_mul(page_size).min(items.len());
+        let end = start.saturating_add(page_size).min(items.len());
+
+        let page = Page {
+            paging: self.clone(),
+            pages,
+        };
+        (items.drain(start..end).collect(), page)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, PartialOrd, Eq, Ord, Hash)]
@@ -102,6 +150,25 @@ lazy_static! {
 
 }
 
+impl JsonSchema for Nevra {
+    fn schema_name() -> String {
+        "Nevra".to_string()
+    }
+
+    fn json_schema(_: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        use schemars::schema::*;
+
+        SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            string: Some(Box::new(StringValidation {
+                pattern: Some(NEVRA_RE.as_str().to_string()),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }.into()
+    }
+}
+
 impl FromStr for Nevra {
     type Err = ();
 
@@ -121,3 +188,58 @@ impl FromStr for Nevra {
         Err(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paging(page: usize, page_size: usize) -> PagingInfo {
+        PagingInfo { page, page_size }
+    }
+
+    #[test]
+    fn paginate_sorts_and_dedups() {
+        let (items, page) = paging(1, 10).paginate(vec![3, 1, 2, 3, 1]);
+        assert_eq!(items, vec![1, 2, 3]);
+        assert_eq!(page.pages, 1);
+    }
+
+    #[test]
+    fn paginate_pages() {
+        let items: Vec<_> = (1..=7).collect();
+        assert_eq!(paging(1, 3).paginate(items.clone()).0, vec![1, 2, 3]);
+        assert_eq!(paging(3, 3).paginate(items.clone()).0, vec![7]);
+        assert_eq!(paging(3, 3).paginate(items).1.pages, 3);
+    }
+
+    #[test]
+    fn paginate_out_of_range() {
+        let items: Vec<_> = (1..=7).collect();
+        let (res, page) = paging(4, 3).paginate(items.clone());
+        assert!(res.is_empty());
+        assert_eq!(page.pages, 3);
+        assert!(paging(usize::MAX, usize::MAX).paginate(items.clone()).0.is_empty());
+        assert!(paging(usize::MAX, 2).paginate(items).0.is_empty());
+        assert_eq!(paging(1, 3).paginate(Vec::<i32>::new()).1.pages, 0);
+    }
+
+    #[test]
+    fn paginate_zero_page_and_size() {
+        let items: Vec<_> = (1..=3).collect();
+        assert_eq!(paging(0, 2).paginate(items.clone()).0, vec![1, 2]);
+        let (res, page) = paging(1, 0).paginate(items);
+        assert_eq!(res, vec![1]);
+        assert_eq!(page.pages, 3);
+    }
+
+    #[test]
+    fn limit_clamps_page_size() {
+        let mut info = paging(0, 10_000);
+        info.limit(100);
+        assert_eq!((info.page, info.page_size), (1, 100));
+
+        let mut info = paging(2, 0);
+        info.limit(100);
+        assert_eq!((info.page, info.page_size), (2, 1));
+    }
+}
@@ -0,0 +1,103 @@
+//! Small database exported the same way as by reposcan, shared by the unit tests.
+//! Available to other crates with the `testing` feature.
+//!
+//! `kernel` has updates in both RHEL repositories and a newer one in a repository of another
+//! product, `postgresql` has a modular update tied to the first of two ids of its stream.
+
+use crate::prelude::*;
+use crate::Cache;
+
+use rusqlite::Connection;
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const SCHEMA: &str = "
+create table packagename(id int, packagename text);
+create table updates(name_id int, package_id int, package_order int);
+create table updates_index(name_id int, evr_id int, package_order int);
+create table evr(id int, epoch int, version text, release text);
+create table arch(id int, arch text);
+create table arch_compat(from_arch_id int, to_arch_id int);
+create table package_detail(id int, name_id int, evr_id int, arch_id int, summary_id int, description_id int, source_package_id int);
+create table repo_detail(id int, label text, name text, url text, basearch text, releasever text, product text, product_id int, revision text);
+create table pkg_repo(pkg_id int, repo_id int);
+create table errata_detail(id int, name text, synopsis text, summary text, type text, severity text, description text, solution text, issued text, updated text, url text);
+create table pkg_errata(pkg_id int, errata_id int);
+create table errata_repo(errata_id int, repo_id int);
+create table cve_detail(id int, name text, redhat_url text, secondary_url text, cvss3_score real, cvss3_metrics text, impact text, published_date text, modified_date text, iava text, description text, cvss2_score real, cvss2_metrics text, source text);
+create table cve_cwe(cve_id int, cwe text);
+create table errata_cve(cve_id int, errata_id int);
+create table cve_pkg(cve_id int, pkg_id int);
+create table dbchange(errata_changes text, cve_changes text, repository_changes text, last_change text, exported text);
+create table errata_bugzilla(errata_id int, bugzilla text);
+create table errata_refs(errata_id int, ref text);
+create table module_stream(module text, stream text, stream_id int);
+create table errata_modulepkg(pkg_id int, errata_id int, module_stream_id int);
+create table string(id int, string text);
+pragma user_version = 3;
+";
+
+const DATA: &str = "
+insert into packagename values (1, 'kernel'), (2, 'postgresql');
+insert into evr values (1, 0, '1.0', '1.el7'), (2, 0, '1.1', '1.el7'), (3, 0, '1.2', '1.el7'), (4, 0, '1.3', '1.el7'),
+    (5, 0, '9.6.1', '1.module'), (6, 0, '9.6.2', '1.module');
+insert into arch values (1, 'x86_64'), (2, 'noarch'), (3, 'i686');
+insert into arch_compat values (1, 1), (1, 2), (3, 3);
+insert into package_detail values (1, 1, 1, 1, 1, null, null), (2, 1, 2, 1, 1, null, null), (3, 1, 3, 1, 1, null, null),
+    (4, 1, 4, 1, 1, null, null), (5, 2, 5, 1, null, null, null), (6, 2, 6, 1, null, null, null);
+insert into updates values (1, 1, 0), (1, 2, 1), (1, 3, 2), (1, 4, 3), (2, 5, 0), (2, 6, 1);
+insert into updates_index values (1, 1, 0), (1, 2, 1), (1, 3, 2), (1, 4, 3), (2, 5, 0), (2, 6, 1);
+insert into repo_detail values
+    (1, 'rhel-7-server-rpms', 'RHEL 7', 'https://cdn/rhel/7Server/x86_64', 'x86_64', '7Server', 'RHEL', 1, '2020-01-01 10:00:00+00:00'),
+    (2, 'rhel-7-server-extras-rpms', 'RHEL 7 extras', 'https://cdn/rhel/7Server/x86_64/extras', 'x86_64', '7Server', 'RHEL', 1, '2020-02-01 10:00:00+00:00'),
+    (3, 'other-7-rpms', 'Other 7', 'https://cdn/other/7Server/x86_64', 'x86_64', '7Server', 'Other', 2, null);
+insert into pkg_repo values (1, 1), (2, 1), (3, 1), (3, 2), (4, 3), (5, 1), (6, 1);
+insert into errata_detail values
+    (1, 'RHSA-2020:0001', 'kernel fix', 'summary', 'security', 'Important', null, 'solution', '2020-01-02 00:00:00+00:00', '2020-01-03 00:00:00+00:00', 'https://e/1'),
+    (2, 'RHBA-2020:0002', 'kernel bug fix', 'summary', 'bugfix', 'None', null, 'solution', '2020-03-02 00:00:00+00:00', '2020-03-05 00:00:00+00:00', 'https://e/2'),
+    (3, 'RHEA-2020:0003', 'postgresql update', 'summary', 'enhancement', 'None', null, 'solution', '2020-03-02 00:00:00+00:00', '2020-03-02 00:00:00+00:00', 'https://e/3'),
+    (4, 'OTHER-2020:0004', 'kernel fix', 'summary', 'security', 'Critical', null, 'solution', '2020-04-01 00:00:00+00:00', '2020-04-01 00:00:00+00:00', 'https://e/4');
+insert into pkg_errata values (2, 1), (3, 2), (4, 4), (6, 3);
+insert into errata_repo values (1, 1), (2, 1), (2, 2), (3, 1), (4, 3);
+insert into cve_detail values
+    (1, 'CVE-2020-0001', null, null, 7.5, 'CVSS:3.0/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:N/A:N', 'Important', '2020-01-01 00:00:00+00:00', '2020-01-05 00:00:00+00:00', null, null, 5.0, 'AV:N/AC:L/Au:N/C:P/I:N/A:N', 'Red Hat'),
+    (2, 'CVE-2020-0002', null, null, 3.3, 'CVSS:3.1/AV:L/AC:H/PR:L/UI:R/S:U/C:L/I:L/A:N', 'Low', '2020-02-01 00:00:00+00:00', null, null, null, null, null, 'Red Hat'),
+    (3, 'CVE-2020-0003', null, null, 9.8, 'CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H', 'Critical', null, null, null, null, null, null, 'Red Hat'),
+    (4, 'CVE-2020-0004', null, null, null, null, 'Moderate', '2020-05-01 00:00:00+00:00', '2020-05-02 00:00:00+00:00', null, null, null, null, 'Red Hat');
+insert into cve_cwe values (1, 'CWE-79'), (2, 'CWE-20'), (3, 'CWE-79');
+insert into errata_cve values (1, 1), (2, 2), (3, 4);
+insert into cve_pkg values (1, 2), (2, 3), (3, 4);
+insert into dbchange values ('2020-04-01 00:00:00+00:00', '2020-05-02 00:00:00+00:00', '2020-02-01 10:00:00+00:00', '2020-05-02 00:00:00+00:00', '2020-05-03 12:00:00.123456+00:00');
+insert into module_stream values ('postgresql', '9.6', 10), ('postgresql', '9.6', 11);
+insert into errata_modulepkg values (6, 3, 10);
+";
+
+/// Writes the fixture database to `path`, replacing the file
+pub fn write_db(path: &Path) -> Result<()> {
+    let _ = std::fs::remove_file(path);
+    let db = Connection::open(path)?;
+    db.execute_batch(SCHEMA)?;
+    db.execute_batch(DATA)?;
+    Ok(())
+}
+
+/// Path in the temporary directory unique within the test run
+pub fn temp_path(name: &str) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!("upcache-test-{}-{}-{}", std::process::id(), n, name))
+}
+
+/// Writes the fixture into a temporary file, and loads it with the regular loader
+pub fn load() -> Result<Cache> {
+    let path = temp_path("vmaas.db");
+    write_db(&path)?;
+    let cache = crate::cache::load(path.to_str().unwrap());
+    std::fs::remove_file(&path)?;
+    cache
+}
+
+lazy_static! {
+    pub static ref CACHE: Cache = load().unwrap();
+}
@@ -0,0 +1,95 @@
+//! Timestamps stored in the database and accepted in requests.
+//!
+//! Reposcan exports timestamps as `2020-01-10 10:12:13.123456+00:00`, clients usually send
+//! RFC 3339 or plain dates. All of them are normalized to UTC and serialized as ISO-8601.
+
+use crate::prelude::*;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, SecondsFormat, Utc};
+
+pub type Timestamp = DateTime<Utc>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimestampError(pub String);
+
+impl fmt::Display for TimestampError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid timestamp {:?}, expected ISO-8601, e.g. 2020-01-10T10:12:13+00:00", self.0)
+    }
+}
+
+impl Error for TimestampError {}
+
+/// Parses a timestamp with an optional offset, values without one are taken as UTC, plain dates
+/// as their midnight
+pub fn parse(value: &str) -> Result<Timestamp, TimestampError> {
+    let value = value.trim();
+    let normalized = value.replacen(' ', "T", 1);
+    if let Ok(ts) = DateTime::parse_from_rfc3339(&normalized) {
+        return Ok(ts.with_timezone(&Utc));
+    }
+    if let Ok(ts) = NaiveDateTime::parse_from_str(&normalized, "%Y-%m-%dT%H:%M:%S%.f") {
+        return Ok(DateTime::from_utc(ts, Utc));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(DateTime::from_utc(date.and_hms(0, 0, 0), Utc));
+    }
+    Err(TimestampError(value.to_string()))
+}
+
+/// ISO-8601 representation, the same as the serialized one
+pub fn format(ts: &Timestamp) -> String {
+    ts.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+}
+
+/// Deserializes an optional request timestamp with [`parse`]
+pub fn deserialize_opt<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Timestamp>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(value) => parse(&value).map(Some).map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn reposcan_format() {
+        let ts = parse("2020-05-03 12:00:00.123456+00:00").unwrap();
+        assert_eq!(ts, Utc.ymd(2020, 5, 3).and_hms_micro(12, 0, 0, 123_456));
+        assert_eq!(format(&ts), "2020-05-03T12:00:00.123456Z");
+    }
+
+    #[test]
+    fn rfc3339() {
+        assert_eq!(parse("2020-01-10T10:12:13Z").unwrap(), Utc.ymd(2020, 1, 10).and_hms(10, 12, 13));
+        assert_eq!(parse(" 2020-01-10T10:12:13+00:00 ").unwrap(), Utc.ymd(2020, 1, 10).and_hms(10, 12, 13));
+    }
+
+    #[test]
+    fn offset_is_converted_to_utc() {
+        let ts = parse("2020-01-10T01:12:13+02:00").unwrap();
+        assert_eq!(ts, Utc.ymd(2020, 1, 9).and_hms(23, 12, 13));
+        assert_eq!(format(&ts), "2020-01-09T23:12:13Z");
+    }
+
+    #[test]
+    fn without_offset_is_utc() {
+        assert_eq!(parse("2020-01-10T10:12:13").unwrap(), Utc.ymd(2020, 1, 10).and_hms(10, 12, 13));
+        assert_eq!(parse("2020-01-10 10:12:13.5").unwrap(), Utc.ymd(2020, 1, 10).and_hms_milli(10, 12, 13, 500));
+    }
+
+    #[test]
+    fn plain_date_is_midnight() {
+        assert_eq!(parse("2020-01-10").unwrap(), Utc.ymd(2020, 1, 10).and_hms(0, 0, 0));
+    }
+
+    #[test]
+    fn rejected() {
+        for value in ["", "yesterday", "2020-13-45", "2020-01-10T25:00:00Z", "10/01/2020", "1578651133"].iter() {
+            assert_eq!(parse(value), Err(TimestampError(value.to_string())));
+        }
+    }
+}